@@ -0,0 +1,97 @@
+use crate::primitives::SignatureResponse;
+use k256::elliptic_curve::ops::Reduce;
+use k256::elliptic_curve::point::{AffineCoordinates, DecompressPoint};
+use k256::elliptic_curve::scalar::{FromUintUnchecked, IsHigh};
+use k256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use k256::elliptic_curve::subtle::Choice;
+use k256::elliptic_curve::PrimeField;
+use k256::{AffinePoint, EncodedPoint, ProjectivePoint, Scalar, U256};
+use near_sdk::{env, AccountId, CurveType};
+use std::fmt::Write;
+
+// Constant prefix that ensures epsilon derivation values are used specifically for
+// near-mpc-recovery with key derivation protocol vX.Y.Z.
+pub const EPSILON_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 epsilon derivation:";
+
+/// Interprets the bytes as a little-endian integer. This matches how the nodes
+/// turn payloads and derivation hashes into scalars.
+pub fn scalar_from_bytes(bytes: &[u8]) -> Scalar {
+    Scalar::from_uint_unchecked(U256::from_le_slice(bytes))
+}
+
+pub fn near_public_key_to_affine_point(public_key: near_sdk::PublicKey) -> Option<AffinePoint> {
+    let mut bytes = public_key.into_bytes();
+    bytes[0] = 0x04;
+    let point = EncodedPoint::from_bytes(bytes).ok()?;
+    AffinePoint::from_encoded_point(&point).into()
+}
+
+pub fn affine_point_to_near_public_key(point: &AffinePoint) -> near_sdk::PublicKey {
+    // NEAR stores secp256k1 keys as the uncompressed point without the 0x04 tag.
+    let bytes = point.to_encoded_point(false).as_bytes()[1..].to_vec();
+    near_sdk::PublicKey::from_parts(CurveType::SECP256K1, bytes).unwrap()
+}
+
+/// Hex encoding of the compressed SEC1 representation of the point.
+pub fn affine_point_to_sec1_hex(point: &AffinePoint) -> String {
+    point
+        .to_encoded_point(true)
+        .as_bytes()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+pub fn derive_epsilon(predecessor_id: &AccountId, path: &str) -> Scalar {
+    // ',' is ACCOUNT_DATA_SEPARATOR from nearcore that indicate the end
+    // of the accound id in the trie key. We reuse the same constant to
+    // indicate the end of the account id in derivation path.
+    let derivation_path = format!("{EPSILON_DERIVATION_PREFIX}{},{}", predecessor_id, path);
+    scalar_from_bytes(&env::sha256_array(derivation_path.as_bytes()))
+}
+
+pub fn derive_key(public_key: AffinePoint, epsilon: Scalar) -> AffinePoint {
+    (ProjectivePoint::GENERATOR * epsilon + public_key).to_affine()
+}
+
+/// Checks that `signature` is a valid low-s ECDSA signature of `msg_hash` under `public_key`,
+/// and that its recovery id points at the right `R`, so that `public_key` can be recovered
+/// from it.
+pub fn check_recoverable_signature(
+    public_key: &AffinePoint,
+    signature: &SignatureResponse,
+    msg_hash: &[u8; 32],
+) -> bool {
+    if signature.v > 1 {
+        return false;
+    }
+    let Some(s) = Option::<Scalar>::from(Scalar::from_repr(signature.s.into())) else {
+        return false;
+    };
+    if bool::from(s.is_zero()) || bool::from(s.is_high()) {
+        return false;
+    }
+    let Some(big_r) = Option::<AffinePoint>::from(AffinePoint::decompress(
+        &signature.r.into(),
+        Choice::from(signature.v),
+    )) else {
+        return false;
+    };
+    let r = x_coordinate(&big_r);
+    if bool::from(r.is_zero()) {
+        return false;
+    }
+    let s_inv = s.invert().unwrap();
+    let msg_hash = scalar_from_bytes(msg_hash);
+    // Unlike plain ECDSA verification, which only compares the x coordinates, the whole
+    // point has to match for the recovery id to be right.
+    let reproduced = (ProjectivePoint::GENERATOR * (msg_hash * s_inv))
+        + (ProjectivePoint::from(*public_key) * (r * s_inv));
+    reproduced.to_affine() == big_r
+}
+
+fn x_coordinate(point: &AffinePoint) -> Scalar {
+    <Scalar as Reduce<U256>>::reduce_bytes(&point.x())
+}
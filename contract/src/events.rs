@@ -0,0 +1,132 @@
+//! [NEP-297](https://nomicon.io/Standards/EventsFormat) events emitted by the contract.
+//! Every event is logged as `EVENT_JSON:{"standard":"mpc","version":"1.0.0","event":...,"data":...}`.
+
+use crate::primitives::{
+    Config, HashScheme, ParticipantInfo, RequestId, SignPolicyChange, SignatureResponse,
+};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, AccountId, PublicKey};
+
+pub const EVENT_STANDARD: &str = "mpc";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    SignRequested {
+        request_id: RequestId,
+        /// Set for requests made through `sign_batch`.
+        batch_id: Option<RequestId>,
+        predecessor_id: AccountId,
+        path: String,
+        payload: [u8; 32],
+        entropy: [u8; 32],
+    },
+    /// Emitted along with `SignRequested` for requests made through `sign_message`, so that
+    /// what was signed can be audited.
+    MessageHashed {
+        request_id: RequestId,
+        scheme: HashScheme,
+        /// The digest of the message, in the byte order of the hash function.
+        digest: [u8; 32],
+    },
+    SignResponded {
+        request_id: RequestId,
+        responder: AccountId,
+        signature: SignatureResponse,
+    },
+    /// The request was cancelled by its predecessor, or dropped along with a cancelled
+    /// request of the same batch.
+    SignCancelled {
+        request_id: RequestId,
+    },
+    /// The request timed out without a response, or was dropped along with an expired
+    /// request of the same batch.
+    SignExpired {
+        request_id: RequestId,
+    },
+    JoinVoted {
+        voter: AccountId,
+        candidate: AccountId,
+    },
+    LeaveVoted {
+        voter: AccountId,
+        account_id: AccountId,
+    },
+    ThresholdVoted {
+        voter: AccountId,
+        threshold: usize,
+    },
+    /// The threshold was raised without a vote to stay a majority of the participants, as a
+    /// candidate was voted in.
+    ThresholdRaised {
+        old_threshold: usize,
+        threshold: usize,
+    },
+    /// A participant proposed new info that has to be voted for before it applies.
+    ParticipantInfoProposed {
+        participant: ParticipantInfo,
+    },
+    ParticipantInfoUpdated {
+        participant: ParticipantInfo,
+    },
+    /// A voted change to the contract config, e.g. the sign fee, has been applied.
+    ConfigChanged {
+        config: Config,
+    },
+    SignPolicyChanged {
+        change: SignPolicyChange,
+    },
+    PkVoted {
+        voter: AccountId,
+        public_key: PublicKey,
+    },
+    ResharingStarted {
+        old_epoch: u64,
+        new_participants: Vec<AccountId>,
+        threshold: usize,
+    },
+    ResharingFinished {
+        epoch: u64,
+    },
+    ResharingCancelled {
+        epoch: u64,
+    },
+    EpochChanged {
+        epoch: u64,
+    },
+    PauseChanged {
+        paused: bool,
+    },
+}
+
+/// The full event log, as written after the `EVENT_JSON:` prefix.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventLog {
+    pub standard: String,
+    pub version: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Event {
+    pub fn emit(self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD.to_string(),
+            version: EVENT_STANDARD_VERSION.to_string(),
+            event: self,
+        };
+        env::log_str(&format!(
+            "{EVENT_JSON_PREFIX}{}",
+            serde_json::to_string(&log).unwrap()
+        ));
+    }
+
+    /// Parses an event emitted by this contract out of a receipt log. Returns `None` for
+    /// logs that are not events of this standard.
+    pub fn from_log(log: &str) -> Option<Event> {
+        let log: EventLog = serde_json::from_str(log.strip_prefix(EVENT_JSON_PREFIX)?).ok()?;
+        (log.standard == EVENT_STANDARD).then_some(log.event)
+    }
+}
@@ -0,0 +1,161 @@
+//! Storage layouts of previous contract versions. `MpcContract::migrate` reads the state
+//! left behind by one of these and converts it into the current layout.
+
+/// The layout used before the contract state was versioned.
+pub mod v0 {
+    use crate::primitives::{self, hpke, Candidates, ParticipantId, Votes};
+    use crate::{InitializingContractState, ProtocolContractState as CurrentProtocolState};
+    use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+    use near_sdk::collections::LookupMap;
+    use near_sdk::{env, AccountId, PublicKey};
+    use std::collections::{BTreeMap, HashSet};
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub struct MpcContract {
+        pub protocol_state: ProtocolContractState,
+        pub pending_requests: LookupMap<[u8; 32], Option<(String, String)>>,
+    }
+
+    /// The sign requests, keyed by their payload, along with their response once there was
+    /// one. Not carried over by the migration, see `MpcContract::sign_helper`.
+    pub fn pending_requests() -> LookupMap<[u8; 32], Option<(String, String)>> {
+        LookupMap::new(b"m")
+    }
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub struct ParticipantInfo {
+        pub account_id: AccountId,
+        pub url: String,
+        pub cipher_pk: hpke::PublicKey,
+        pub sign_pk: PublicKey,
+    }
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub struct Participants {
+        pub participants: BTreeMap<AccountId, ParticipantInfo>,
+    }
+
+    /// Participants had no ids of their own yet; the nodes numbered them by their position in
+    /// the map, so the same ids are kept.
+    impl From<Participants> for primitives::Participants {
+        fn from(participants: Participants) -> Self {
+            let ids = participants
+                .participants
+                .keys()
+                .enumerate()
+                .map(|(id, account_id)| (account_id.clone(), id as ParticipantId))
+                .collect();
+            participants.with_ids(&ids)
+        }
+    }
+
+    impl Participants {
+        /// Converts the participants, giving each the id it has in `ids`.
+        fn with_ids(self, ids: &BTreeMap<AccountId, ParticipantId>) -> primitives::Participants {
+            let mut converted = primitives::Participants::new();
+            for (account_id, info) in self.participants {
+                converted.insert(
+                    account_id.clone(),
+                    primitives::ParticipantInfo {
+                        id: ids[&account_id],
+                        account_id: info.account_id,
+                        url: info.url,
+                        cipher_pk: info.cipher_pk,
+                        sign_pk: info.sign_pk,
+                    },
+                );
+            }
+            converted.next_id = ids.values().max().map_or(0, |id| id + 1);
+            converted
+        }
+    }
+
+    /// Numbers the participants of a resharing in a single id space, so that an account keeps
+    /// its id across both sets. The old participants keep the ids the nodes gave them, the
+    /// joining ones are numbered after them in the order of their account ids.
+    fn resharing_ids(
+        old_participants: &Participants,
+        new_participants: &Participants,
+    ) -> BTreeMap<AccountId, ParticipantId> {
+        old_participants
+            .participants
+            .keys()
+            .chain(
+                new_participants
+                    .participants
+                    .keys()
+                    .filter(|account_id| !old_participants.participants.contains_key(*account_id)),
+            )
+            .enumerate()
+            .map(|(id, account_id)| (account_id.clone(), id as ParticipantId))
+            .collect()
+    }
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub struct RunningContractState {
+        pub epoch: u64,
+        pub participants: Participants,
+        pub threshold: usize,
+        pub public_key: PublicKey,
+        pub candidates: Candidates,
+        pub join_votes: Votes,
+        pub leave_votes: Votes,
+    }
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub struct ResharingContractState {
+        pub old_epoch: u64,
+        pub old_participants: Participants,
+        pub new_participants: Participants,
+        pub threshold: usize,
+        pub public_key: PublicKey,
+        pub finished_votes: HashSet<AccountId>,
+    }
+
+    #[derive(BorshDeserialize, BorshSerialize)]
+    pub enum ProtocolContractState {
+        NotInitialized,
+        Initializing(InitializingContractState),
+        Running(RunningContractState),
+        Resharing(ResharingContractState),
+    }
+
+    impl From<ProtocolContractState> for CurrentProtocolState {
+        fn from(state: ProtocolContractState) -> Self {
+            match state {
+                ProtocolContractState::NotInitialized => CurrentProtocolState::NotInitialized,
+                ProtocolContractState::Initializing(state) => {
+                    CurrentProtocolState::Initializing(state)
+                }
+                ProtocolContractState::Running(state) => {
+                    // Everything this version did not have starts out as in a fresh epoch.
+                    let mut running = crate::RunningContractState::new(
+                        state.epoch,
+                        state.participants.into(),
+                        state.threshold,
+                        state.public_key,
+                    );
+                    running.candidates = state.candidates;
+                    running.join_votes = state.join_votes;
+                    running.leave_votes = state.leave_votes;
+                    CurrentProtocolState::Running(running)
+                }
+                // The set of participants did not change its threshold in this version.
+                ProtocolContractState::Resharing(state) => {
+                    let ids = resharing_ids(&state.old_participants, &state.new_participants);
+                    CurrentProtocolState::Resharing(crate::ResharingContractState {
+                        old_epoch: state.old_epoch,
+                        old_participants: state.old_participants.with_ids(&ids),
+                        new_participants: state.new_participants.with_ids(&ids),
+                        old_threshold: state.threshold,
+                        threshold: state.threshold,
+                        public_key: state.public_key,
+                        finished_votes: state.finished_votes,
+                        started_at: env::block_height(),
+                        cancel_votes: HashSet::new(),
+                    })
+                }
+            }
+        }
+    }
+}
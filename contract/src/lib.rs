@@ -1,12 +1,47 @@
+pub mod crypto;
+pub mod events;
+pub mod legacy;
 pub mod primitives;
+#[cfg(test)]
+mod tests;
 
+use events::Event;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
+use near_sdk::json_types::Base64VecU8;
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, near_bindgen, AccountId, PanicOnDefault, Promise, PromiseOrValue, PublicKey};
-use primitives::{CandidateInfo, Candidates, Participants, PkVotes, Votes};
+use near_sdk::{
+    env, near_bindgen, AccountId, BlockHeight, Gas, NearToken, PanicOnDefault, Promise,
+    PromiseOrValue, PublicKey, StorageUsage,
+};
+use primitives::{
+    paginate, CandidateInfo, Candidates, CodeHash, Config, DerivedPublicKey, FeeVotes, HashScheme,
+    ParticipantInfo, Participants, PendingRequest, PkVotes, ProposedUpdate, RequestId, SignPolicy,
+    SignPolicyChange, SignPolicyVotes, SignatureResponse, ThresholdVotes, TimeoutVotes, VoteTally,
+    Votes,
+};
 use std::collections::{BTreeMap, HashSet};
 
+/// The lowest threshold participants can vote for.
+pub const MIN_THRESHOLD: usize = 2;
+
+/// The lowest safe threshold for `participants` participants: more than half of them, so that
+/// no two disjoint groups can sign on their own, and never less than `MIN_THRESHOLD`.
+pub fn min_threshold(participants: usize) -> usize {
+    (participants / 2 + 1).max(MIN_THRESHOLD)
+}
+
+/// The largest number of requests `sign_batch` accepts at once.
+pub const MAX_BATCH_SIZE: usize = 16;
+
+/// Version of the storage layout written by this code. Bump it whenever the layout changes
+/// between releases and teach `migrate` how to read the previous one (see [`legacy`]).
+pub const STATE_VERSION: u32 = 1;
+const STATE_VERSION_KEY: &[u8] = b"VERSION";
+
+/// Gas attached to the `migrate` call made right after deploying a voted update.
+const MIGRATE_GAS: Gas = Gas::from_tgas(100);
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
 pub struct InitializingContractState {
     pub candidates: Candidates,
@@ -23,6 +58,50 @@ pub struct RunningContractState {
     pub candidates: Candidates,
     pub join_votes: Votes,
     pub leave_votes: Votes,
+    pub fee_votes: FeeVotes,
+    pub threshold_votes: ThresholdVotes,
+    /// Participant info updates waiting for votes, see `update_participant_info`.
+    pub info_updates: Participants,
+    pub info_update_votes: Votes,
+    pub sign_policy_votes: SignPolicyVotes,
+    pub request_timeout_votes: TimeoutVotes,
+    pub join_deposit_votes: FeeVotes,
+    /// While paused, no new sign requests, candidates or votes are accepted. Requests that
+    /// are already pending can still be responded to, and updates can still be proposed and
+    /// voted for so that a fix can be deployed.
+    pub paused: bool,
+    pub pause_votes: HashSet<AccountId>,
+    pub unpause_votes: HashSet<AccountId>,
+}
+
+impl RunningContractState {
+    /// The state of a freshly started epoch, with no candidates, votes or pause.
+    fn new(
+        epoch: u64,
+        participants: Participants,
+        threshold: usize,
+        public_key: PublicKey,
+    ) -> Self {
+        RunningContractState {
+            epoch,
+            participants,
+            threshold,
+            public_key,
+            candidates: Candidates::new(),
+            join_votes: Votes::new(),
+            leave_votes: Votes::new(),
+            fee_votes: FeeVotes::new(),
+            threshold_votes: ThresholdVotes::new(),
+            info_updates: Participants::new(),
+            info_update_votes: Votes::new(),
+            sign_policy_votes: SignPolicyVotes::new(),
+            request_timeout_votes: TimeoutVotes::new(),
+            join_deposit_votes: FeeVotes::new(),
+            paused: false,
+            pause_votes: HashSet::new(),
+            unpause_votes: HashSet::new(),
+        }
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
@@ -31,9 +110,23 @@ pub struct ResharingContractState {
     pub old_participants: Participants,
     // TODO: only store diff to save on storage
     pub new_participants: Participants,
+    pub old_threshold: usize,
+    /// The threshold of the new participant set.
     pub threshold: usize,
     pub public_key: PublicKey,
     pub finished_votes: HashSet<AccountId>,
+    /// The block height at which the resharing started.
+    pub started_at: BlockHeight,
+    /// Old participants that voted to abort the resharing, see `vote_cancel_resharing`.
+    pub cancel_votes: HashSet<AccountId>,
+}
+
+impl ResharingContractState {
+    /// Whether `account_id` is joining the participant set through this resharing.
+    fn is_joining(&self, account_id: &AccountId) -> bool {
+        self.new_participants.contains_key(account_id)
+            && !self.old_participants.contains_key(account_id)
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
@@ -48,33 +141,103 @@ pub enum ProtocolContractState {
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct MpcContract {
     protocol_state: ProtocolContractState,
-    pending_requests: LookupMap<[u8; 32], Option<(String, String)>>,
+    pending_requests: LookupMap<RequestId, PendingRequest>,
+    pending_request_count: u64,
+    request_nonce: u64,
+    config: Config,
+    proposed_updates: LookupMap<CodeHash, ProposedUpdate>,
+    update_code: LookupMap<CodeHash, Vec<u8>>,
+    /// An account (e.g. a council contract) that can pause and unpause the contract on its own.
+    owner: Option<AccountId>,
+    /// Deposits made by candidates through `join` that have not been refunded yet.
+    candidate_deposits: LookupMap<AccountId, NearToken>,
+    sign_policy: SignPolicy,
+    /// Number of pending requests of each predecessor, for `SignPolicy::max_pending_requests`.
+    predecessor_request_counts: LookupMap<AccountId, u64>,
 }
 
 #[near_bindgen]
 impl MpcContract {
     #[init(ignore_state)]
-    pub fn init(threshold: usize, candidates: BTreeMap<AccountId, CandidateInfo>) -> Self {
+    pub fn init(
+        threshold: usize,
+        candidates: BTreeMap<AccountId, CandidateInfo>,
+        config: Option<Config>,
+        owner: Option<AccountId>,
+    ) -> Self {
+        if threshold > candidates.len() {
+            env::panic_str("threshold is larger than the number of candidates");
+        }
+        if threshold < min_threshold(candidates.len()) {
+            env::panic_str("threshold is too low");
+        }
+        Self::write_state_version();
         MpcContract {
             protocol_state: ProtocolContractState::Initializing(InitializingContractState {
                 candidates: Candidates { candidates },
                 threshold,
                 pk_votes: PkVotes::new(),
             }),
-            pending_requests: LookupMap::new(b"m"),
+            pending_requests: LookupMap::new(b"r"),
+            pending_request_count: 0,
+            request_nonce: 0,
+            config: config.unwrap_or_default(),
+            proposed_updates: LookupMap::new(b"u"),
+            update_code: LookupMap::new(b"c"),
+            owner,
+            candidate_deposits: LookupMap::new(b"d"),
+            sign_policy: SignPolicy::default(),
+            predecessor_request_counts: LookupMap::new(b"p"),
         }
     }
 
+    /// Converts the state left by a previous version of the contract into the current
+    /// layout. Called by the contract itself right after deploying a voted update.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let version = env::storage_read(STATE_VERSION_KEY)
+            .map(|bytes| u32::try_from_slice(&bytes).unwrap())
+            .unwrap_or(0);
+        let contract = match version {
+            // The pending requests of this version are left where they are, they are
+            // drained by the promises still polling them (see `sign_helper`).
+            0 => env::state_read::<legacy::v0::MpcContract>().map(|old| MpcContract {
+                protocol_state: old.protocol_state.into(),
+                pending_requests: LookupMap::new(b"r"),
+                pending_request_count: 0,
+                request_nonce: 0,
+                config: Config::default(),
+                proposed_updates: LookupMap::new(b"u"),
+                update_code: LookupMap::new(b"c"),
+                owner: None,
+                candidate_deposits: LookupMap::new(b"d"),
+                sign_policy: SignPolicy::default(),
+                predecessor_request_counts: LookupMap::new(b"p"),
+            }),
+            STATE_VERSION => env::state_read::<Self>(),
+            _ => env::panic_str(&format!("unknown state version {version}")),
+        };
+        let contract = contract.unwrap_or_else(|| env::panic_str("contract state is missing"));
+        Self::write_state_version();
+        contract
+    }
+
     pub fn state(self) -> ProtocolContractState {
         self.protocol_state
     }
 
+    /// Registers the caller as a candidate to join the participant set. The attached deposit
+    /// has to cover `join_deposit`. It is refunded once the resharing the candidate is voted
+    /// into has finished or is cancelled, or when the candidate calls `withdraw_candidacy`.
+    #[payable]
     pub fn join(
         &mut self,
         url: String,
         cipher_pk: primitives::hpke::PublicKey,
         sign_pk: PublicKey,
     ) {
+        self.require_not_paused();
         match &mut self.protocol_state {
             ProtocolContractState::Running(RunningContractState {
                 participants,
@@ -85,6 +248,21 @@ impl MpcContract {
                 if participants.contains_key(&signer_account_id) {
                     env::panic_str("this participant is already in the participant set");
                 }
+                let deposit = env::attached_deposit();
+                if deposit < self.config.join_deposit {
+                    env::panic_str(&format!(
+                        "attached deposit {deposit} is less than the required {}",
+                        self.config.join_deposit
+                    ));
+                }
+                // Candidates dropped by a resharing may join again without withdrawing first.
+                let total_deposit = self
+                    .candidate_deposits
+                    .get(&signer_account_id)
+                    .unwrap_or(NearToken::from_yoctonear(0))
+                    .saturating_add(deposit);
+                self.candidate_deposits
+                    .insert(&signer_account_id, &total_deposit);
                 candidates.insert(
                     signer_account_id.clone(),
                     CandidateInfo {
@@ -100,6 +278,7 @@ impl MpcContract {
     }
 
     pub fn vote_join(&mut self, candidate_account_id: AccountId) -> bool {
+        self.require_not_paused();
         match &mut self.protocol_state {
             ProtocolContractState::Running(RunningContractState {
                 epoch,
@@ -117,20 +296,42 @@ impl MpcContract {
                 let candidate_info = candidates
                     .get(&candidate_account_id)
                     .unwrap_or_else(|| env::panic_str("candidate is not registered"));
+                Event::JoinVoted {
+                    voter: signer_account_id.clone(),
+                    candidate: candidate_account_id.clone(),
+                }
+                .emit();
                 let voted = join_votes.entry(candidate_account_id.clone());
                 voted.insert(signer_account_id);
                 if voted.len() >= *threshold {
                     let mut new_participants = participants.clone();
-                    new_participants
-                        .insert(candidate_account_id.clone(), candidate_info.clone().into());
+                    new_participants.admit(candidate_info.clone());
+                    // A larger participant set may need a larger threshold to stay safe.
+                    let new_threshold = (*threshold).max(min_threshold(new_participants.len()));
+                    if new_threshold != *threshold {
+                        Event::ThresholdRaised {
+                            old_threshold: *threshold,
+                            threshold: new_threshold,
+                        }
+                        .emit();
+                    }
+                    Event::ResharingStarted {
+                        old_epoch: *epoch,
+                        new_participants: new_participants.keys().cloned().collect(),
+                        threshold: new_threshold,
+                    }
+                    .emit();
                     self.protocol_state =
                         ProtocolContractState::Resharing(ResharingContractState {
                             old_epoch: *epoch,
                             old_participants: participants.clone(),
                             new_participants,
-                            threshold: *threshold,
+                            old_threshold: *threshold,
+                            threshold: new_threshold,
                             public_key: public_key.clone(),
                             finished_votes: HashSet::new(),
+                            started_at: env::block_height(),
+                            cancel_votes: HashSet::new(),
                         });
                     true
                 } else {
@@ -142,6 +343,7 @@ impl MpcContract {
     }
 
     pub fn vote_leave(&mut self, acc_id_to_leave: AccountId) -> bool {
+        self.require_not_paused();
         match &mut self.protocol_state {
             ProtocolContractState::Running(RunningContractState {
                 epoch,
@@ -158,19 +360,36 @@ impl MpcContract {
                 if !participants.contains_key(&acc_id_to_leave) {
                     env::panic_str("account to leave is not in the participant set");
                 }
+                if participants.len() - 1 < *threshold {
+                    env::panic_str("not enough participants would be left to reach the threshold");
+                }
+                Event::LeaveVoted {
+                    voter: signer_account_id.clone(),
+                    account_id: acc_id_to_leave.clone(),
+                }
+                .emit();
                 let voted = leave_votes.entry(acc_id_to_leave.clone());
                 voted.insert(signer_account_id);
                 if voted.len() >= *threshold {
                     let mut new_participants = participants.clone();
                     new_participants.remove(&acc_id_to_leave);
+                    Event::ResharingStarted {
+                        old_epoch: *epoch,
+                        new_participants: new_participants.keys().cloned().collect(),
+                        threshold: *threshold,
+                    }
+                    .emit();
                     self.protocol_state =
                         ProtocolContractState::Resharing(ResharingContractState {
                             old_epoch: *epoch,
                             old_participants: participants.clone(),
                             new_participants,
+                            old_threshold: *threshold,
                             threshold: *threshold,
                             public_key: public_key.clone(),
                             finished_votes: HashSet::new(),
+                            started_at: env::block_height(),
+                            cancel_votes: HashSet::new(),
                         });
                     true
                 } else {
@@ -181,6 +400,207 @@ impl MpcContract {
         }
     }
 
+    /// Removes the caller from the candidates, drops the votes for it to join and refunds its
+    /// deposit.
+    pub fn withdraw_candidacy(&mut self) {
+        let signer_account_id = env::signer_account_id();
+        let mut was_candidate = false;
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                candidates,
+                join_votes,
+                ..
+            }) => {
+                was_candidate = candidates.contains_key(&signer_account_id);
+                candidates.remove(&signer_account_id);
+                join_votes.votes.remove(&signer_account_id);
+            }
+            ProtocolContractState::Resharing(state) if state.is_joining(&signer_account_id) => {
+                env::panic_str("candidate is being reshared into the participant set");
+            }
+            _ => {}
+        }
+        let deposit = self.candidate_deposits.remove(&signer_account_id);
+        if !was_candidate && deposit.is_none() {
+            env::panic_str("calling account is not a candidate");
+        }
+        if let Some(deposit) = deposit.filter(|deposit| !deposit.is_zero()) {
+            Promise::new(signer_account_id).transfer(deposit);
+        }
+    }
+
+    /// Takes back the vote the caller cast for `account_id` to join or to leave.
+    pub fn revoke_vote(&mut self, account_id: AccountId) {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                join_votes,
+                leave_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                // An account is either a candidate or a participant, so at most one of these
+                // can hold a vote.
+                let revoked = join_votes.remove_vote(&account_id, &signer_account_id)
+                    || leave_votes.remove_vote(&account_id, &signer_account_id);
+                if !revoked {
+                    env::panic_str("calling account has not voted for this account");
+                }
+            }
+            _ => env::panic_str("protocol state can't revoke votes right now"),
+        }
+    }
+
+    pub fn vote_threshold(&mut self, new_threshold: usize) -> bool {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                epoch,
+                participants,
+                threshold,
+                public_key,
+                threshold_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                if new_threshold == *threshold {
+                    env::panic_str("threshold is already set to this value");
+                }
+                if new_threshold > participants.len() {
+                    env::panic_str("threshold is larger than the number of participants");
+                }
+                if new_threshold < min_threshold(participants.len()) {
+                    env::panic_str("threshold is too low");
+                }
+                Event::ThresholdVoted {
+                    voter: signer_account_id.clone(),
+                    threshold: new_threshold,
+                }
+                .emit();
+                let voted = threshold_votes.entry(new_threshold);
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    Event::ResharingStarted {
+                        old_epoch: *epoch,
+                        new_participants: participants.keys().cloned().collect(),
+                        threshold: new_threshold,
+                    }
+                    .emit();
+                    self.protocol_state =
+                        ProtocolContractState::Resharing(ResharingContractState {
+                            old_epoch: *epoch,
+                            old_participants: participants.clone(),
+                            new_participants: participants.clone(),
+                            old_threshold: *threshold,
+                            threshold: new_threshold,
+                            public_key: public_key.clone(),
+                            finished_votes: HashSet::new(),
+                            started_at: env::block_height(),
+                            cancel_votes: HashSet::new(),
+                        });
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => env::panic_str("protocol state can't change the threshold right now"),
+        }
+    }
+
+    /// Updates the url and keys the calling participant is reachable with. Takes effect right
+    /// away unless `participant_update_requires_vote` is set, in which case the update waits
+    /// for `threshold` participants to approve it through `vote_participant_info`.
+    pub fn update_participant_info(
+        &mut self,
+        url: String,
+        cipher_pk: primitives::hpke::PublicKey,
+        sign_pk: PublicKey,
+    ) -> bool {
+        let requires_vote = self.config.participant_update_requires_vote;
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                info_updates,
+                info_update_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                let Some(current_info) = participants.get(&signer_account_id) else {
+                    env::panic_str("calling account is not in the participant set");
+                };
+                let participant_info = ParticipantInfo {
+                    id: current_info.id,
+                    account_id: signer_account_id.clone(),
+                    url,
+                    cipher_pk,
+                    sign_pk,
+                };
+                if requires_vote {
+                    Event::ParticipantInfoProposed {
+                        participant: participant_info.clone(),
+                    }
+                    .emit();
+                    // Votes for a previously proposed update do not carry over.
+                    info_update_votes.votes.remove(&signer_account_id);
+                    info_updates.insert(signer_account_id, participant_info);
+                    false
+                } else {
+                    Event::ParticipantInfoUpdated {
+                        participant: participant_info.clone(),
+                    }
+                    .emit();
+                    participants.insert(signer_account_id, participant_info);
+                    true
+                }
+            }
+            _ => env::panic_str("protocol state can't update participants right now"),
+        }
+    }
+
+    pub fn vote_participant_info(&mut self, account_id: AccountId) -> bool {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                threshold,
+                info_updates,
+                info_update_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                let participant_info = info_updates
+                    .get(&account_id)
+                    .cloned()
+                    .unwrap_or_else(|| env::panic_str("participant has not proposed an update"));
+                let voted = info_update_votes.entry(account_id.clone());
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    Event::ParticipantInfoUpdated {
+                        participant: participant_info.clone(),
+                    }
+                    .emit();
+                    info_updates.remove(&account_id);
+                    info_update_votes.votes.remove(&account_id);
+                    participants.insert(account_id, participant_info);
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => env::panic_str("protocol state can't update participants right now"),
+        }
+    }
+
     pub fn vote_pk(&mut self, public_key: PublicKey) -> bool {
         match &mut self.protocol_state {
             ProtocolContractState::Initializing(InitializingContractState {
@@ -192,18 +612,22 @@ impl MpcContract {
                 if !candidates.contains_key(&signer_account_id) {
                     env::panic_str("calling account is not in the participant set");
                 }
+                Event::PkVoted {
+                    voter: signer_account_id.clone(),
+                    public_key: public_key.clone(),
+                }
+                .emit();
                 let voted = pk_votes.entry(public_key.clone());
                 voted.insert(signer_account_id);
                 if voted.len() >= *threshold {
-                    self.protocol_state = ProtocolContractState::Running(RunningContractState {
-                        epoch: 0,
-                        participants: candidates.clone().into(),
-                        threshold: *threshold,
-                        public_key,
-                        candidates: Candidates::new(),
-                        join_votes: Votes::new(),
-                        leave_votes: Votes::new(),
-                    });
+                    Event::EpochChanged { epoch: 0 }.emit();
+                    self.protocol_state =
+                        ProtocolContractState::Running(RunningContractState::new(
+                            0,
+                            candidates.clone().into(),
+                            *threshold,
+                            public_key,
+                        ));
                     true
                 } else {
                     false
@@ -221,9 +645,11 @@ impl MpcContract {
                 old_epoch,
                 old_participants,
                 new_participants,
+                old_threshold,
                 threshold,
                 public_key,
                 finished_votes,
+                ..
             }) => {
                 if *old_epoch + 1 != epoch {
                     env::panic_str("mismatched epochs");
@@ -233,16 +659,26 @@ impl MpcContract {
                     env::panic_str("calling account is not in the old participant set");
                 }
                 finished_votes.insert(signer_account_id);
-                if finished_votes.len() >= *threshold {
-                    self.protocol_state = ProtocolContractState::Running(RunningContractState {
-                        epoch: *old_epoch + 1,
-                        participants: new_participants.clone(),
-                        threshold: *threshold,
-                        public_key: public_key.clone(),
-                        candidates: Candidates::new(),
-                        join_votes: Votes::new(),
-                        leave_votes: Votes::new(),
-                    });
+                if finished_votes.len() >= *old_threshold {
+                    for account_id in new_participants.keys() {
+                        if old_participants.contains_key(account_id) {
+                            continue;
+                        }
+                        if let Some(deposit) = self.candidate_deposits.remove(account_id) {
+                            if !deposit.is_zero() {
+                                Promise::new(account_id.clone()).transfer(deposit);
+                            }
+                        }
+                    }
+                    Event::ResharingFinished { epoch }.emit();
+                    Event::EpochChanged { epoch }.emit();
+                    self.protocol_state =
+                        ProtocolContractState::Running(RunningContractState::new(
+                            *old_epoch + 1,
+                            new_participants.clone(),
+                            *threshold,
+                            public_key.clone(),
+                        ));
                     true
                 } else {
                     false
@@ -259,43 +695,682 @@ impl MpcContract {
         }
     }
 
-    #[allow(unused_variables)]
+    /// Votes to abort the resharing from `epoch`, putting the old participants back in
+    /// charge at that epoch. Once the resharing has been going on for longer than
+    /// `resharing_timeout_blocks`, a single vote from an old participant is enough.
+    pub fn vote_cancel_resharing(&mut self, epoch: u64) -> bool {
+        let timeout = self.config.resharing_timeout_blocks;
+        match &mut self.protocol_state {
+            ProtocolContractState::Resharing(ResharingContractState {
+                old_epoch,
+                old_participants,
+                new_participants,
+                old_threshold,
+                public_key,
+                started_at,
+                cancel_votes,
+                ..
+            }) => {
+                if *old_epoch != epoch {
+                    env::panic_str("mismatched epochs");
+                }
+                let signer_account_id = env::signer_account_id();
+                if !old_participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the old participant set");
+                }
+                cancel_votes.insert(signer_account_id);
+                let expired = env::block_height() > *started_at + timeout;
+                if expired || cancel_votes.len() >= *old_threshold {
+                    // Candidates that were voted in get their deposit back, since they are
+                    // dropped from the candidates along with the cancelled resharing.
+                    for account_id in new_participants.keys() {
+                        if old_participants.contains_key(account_id) {
+                            continue;
+                        }
+                        if let Some(deposit) = self.candidate_deposits.remove(account_id) {
+                            if !deposit.is_zero() {
+                                Promise::new(account_id.clone()).transfer(deposit);
+                            }
+                        }
+                    }
+                    // Ids handed out to the cancelled joiners stay used.
+                    let mut participants = old_participants.clone();
+                    participants.next_id = participants.next_id.max(new_participants.next_id);
+                    Event::ResharingCancelled { epoch }.emit();
+                    self.protocol_state =
+                        ProtocolContractState::Running(RunningContractState::new(
+                            *old_epoch,
+                            participants,
+                            *old_threshold,
+                            public_key.clone(),
+                        ));
+                    true
+                } else {
+                    false
+                }
+            }
+            ProtocolContractState::Running(state) => {
+                if state.epoch == epoch {
+                    true
+                } else {
+                    env::panic_str("protocol is not resharing right now")
+                }
+            }
+            _ => env::panic_str("protocol is not resharing right now"),
+        }
+    }
+
+    /// Whether the current resharing has timed out and can be aborted by any old participant.
+    pub fn resharing_expired(&self) -> bool {
+        match &self.protocol_state {
+            ProtocolContractState::Resharing(state) => {
+                env::block_height() > state.started_at + self.config.resharing_timeout_blocks
+            }
+            _ => false,
+        }
+    }
+
+    /// Votes to pause the contract. Once `threshold` participants have voted for it, new sign
+    /// requests, candidates and votes are rejected until the contract is unpaused. Pausing is
+    /// only possible while the protocol is running, so this panics during initialization and
+    /// resharing.
+    pub fn vote_pause(&mut self) -> bool {
+        self.vote_paused(true)
+    }
+
+    /// Votes to resume normal operation of a paused contract. Like `vote_pause`, this is only
+    /// possible while the protocol is running.
+    pub fn vote_unpause(&mut self) -> bool {
+        self.vote_paused(false)
+    }
+
+    /// Pauses the contract right away. Can only be called by the owner, and only while the
+    /// protocol is running.
+    pub fn pause(&mut self) {
+        self.require_owner();
+        self.set_paused(true);
+    }
+
+    /// Unpauses the contract right away. Can only be called by the owner, and only while the
+    /// protocol is running.
+    pub fn unpause(&mut self) {
+        self.require_owner();
+        self.set_paused(false);
+    }
+
+    /// Hands the owner role over to another account, or removes it. Can be called by the
+    /// current owner or by the contract account itself.
+    pub fn set_owner(&mut self, owner: Option<AccountId>) {
+        if env::predecessor_account_id() != env::current_account_id() {
+            self.require_owner();
+        }
+        self.owner = owner;
+    }
+
+    pub fn owner(&self) -> Option<AccountId> {
+        self.owner.clone()
+    }
+
+    pub fn paused(&self) -> bool {
+        matches!(&self.protocol_state, ProtocolContractState::Running(state) if state.paused)
+    }
+
+    /// Requests a signature of `payload` with the key derived from the caller and `path`.
+    /// The attached deposit has to cover the storage used by the request plus the protocol
+    /// fee (see `sign_fee`). The rest is refunded once the request is responded to.
+    /// `payload` is read as a little-endian integer, see `SignatureResponse` for what that
+    /// means for the signed digest.
+    #[payable]
     pub fn sign(&mut self, payload: [u8; 32], path: String) -> Promise {
-        match self.pending_requests.get(&payload) {
+        self.require_not_paused();
+        let deposit = env::attached_deposit();
+        let storage_before = env::storage_usage();
+        let request_id =
+            self.add_pending_request(payload, path, deposit, env::random_seed_array(), None);
+        self.require_sign_deposit(deposit, storage_before, 1);
+        Self::ext(env::current_account_id()).sign_request_helper(request_id, 0)
+    }
+
+    /// Requests a signature of `message`, hashed on chain with `scheme`. The resulting
+    /// signature is a plain ECDSA signature of the digest, so it can be checked against the
+    /// message by anyone knowing the scheme. Deposits work the same as for `sign`.
+    #[payable]
+    pub fn sign_message(
+        &mut self,
+        message: Base64VecU8,
+        scheme: HashScheme,
+        path: String,
+    ) -> Promise {
+        self.require_not_paused();
+        let deposit = env::attached_deposit();
+        let storage_before = env::storage_usage();
+        let digest = scheme.digest(&message.0);
+        // The nodes read payloads as little-endian integers while ECDSA reads digests as
+        // big-endian ones.
+        let mut payload = digest;
+        payload.reverse();
+        let request_id =
+            self.add_pending_request(payload, path, deposit, env::random_seed_array(), None);
+        Event::MessageHashed {
+            request_id,
+            scheme,
+            digest,
+        }
+        .emit();
+        self.require_sign_deposit(deposit, storage_before, 1);
+        Self::ext(env::current_account_id()).sign_request_helper(request_id, 0)
+    }
+
+    /// Requests signatures for several payloads at once. Resolves to the signatures in the
+    /// order of `requests` once all of them are available, and fails if any of them fails.
+    /// The attached deposit has to cover the storage and fee of every request and is split
+    /// evenly between them. The batch is identified by the id of its first request.
+    #[payable]
+    pub fn sign_batch(&mut self, requests: Vec<([u8; 32], String)>) -> Promise {
+        self.require_not_paused();
+        if requests.is_empty() {
+            env::panic_str("batch is empty");
+        }
+        if requests.len() > MAX_BATCH_SIZE {
+            env::panic_str(&format!(
+                "batch is larger than the maximum of {MAX_BATCH_SIZE} requests"
+            ));
+        }
+        let deposit = env::attached_deposit();
+        let count = requests.len() as u128;
+        let share = deposit.as_yoctonear() / count;
+        let remainder = deposit.as_yoctonear() % count;
+        let random_seed = env::random_seed_array();
+        let (first_payload, first_path) = &requests[0];
+        let batch_id = primitives::request_id(
+            &env::predecessor_account_id(),
+            first_path,
+            first_payload,
+            self.request_nonce,
+        );
+        let storage_before = env::storage_usage();
+        let mut request_ids = Vec::with_capacity(requests.len());
+        for (index, (payload, path)) in requests.into_iter().enumerate() {
+            // All requests of the batch share the same receipt, so each of them needs its
+            // own entropy.
+            let entropy = env::sha256_array(&borsh::to_vec(&(random_seed, index as u64)).unwrap());
+            let request_deposit = if index == 0 { share + remainder } else { share };
+            request_ids.push(self.add_pending_request(
+                payload,
+                path,
+                NearToken::from_yoctonear(request_deposit),
+                entropy,
+                Some(batch_id),
+            ));
+        }
+        self.require_sign_deposit(deposit, storage_before, count);
+        Self::ext(env::current_account_id()).sign_batch_helper(request_ids, 0)
+    }
+
+    #[private]
+    pub fn sign_request_helper(
+        &mut self,
+        request_id: RequestId,
+        depth: usize,
+    ) -> PromiseOrValue<SignatureResponse> {
+        let Some(request) = self.pending_requests.get(&request_id) else {
+            env::panic_str("sign request has been cancelled");
+        };
+        match request.response {
+            Some(signature) => {
+                self.remove_pending_request(&request_id);
+                let refund = request.deposit.saturating_sub(request.fee);
+                if !refund.is_zero() {
+                    Promise::new(request.predecessor_id).transfer(refund);
+                }
+                PromiseOrValue::Value(signature)
+            }
+            None if self.is_expired(&request) => {
+                self.remove_pending_request(&request_id);
+                Event::SignExpired { request_id }.emit();
+                PromiseOrValue::Promise(Self::refund_and_fail(
+                    request.predecessor_id,
+                    request.deposit,
+                    "sign request has timed out",
+                ))
+            }
             None => {
-                self.pending_requests.insert(&payload, &None);
-                env::log_str(&serde_json::to_string(&near_sdk::env::random_seed_array()).unwrap());
-                Self::ext(env::current_account_id()).sign_helper(payload, 0)
+                env::log_str(&format!("not ready yet (depth={})", depth));
+                let account_id = env::current_account_id();
+                PromiseOrValue::Promise(
+                    Self::ext(account_id).sign_request_helper(request_id, depth + 1),
+                )
+            }
+        }
+    }
+
+    #[private]
+    pub fn sign_batch_helper(
+        &mut self,
+        request_ids: Vec<RequestId>,
+        depth: usize,
+    ) -> PromiseOrValue<Vec<SignatureResponse>> {
+        let requests: Vec<Option<PendingRequest>> = request_ids
+            .iter()
+            .map(|request_id| self.pending_requests.get(request_id))
+            .collect();
+        let failure = if requests.iter().any(Option::is_none) {
+            Some(("sign request has been cancelled", false))
+        } else if requests
+            .iter()
+            .flatten()
+            .any(|request| request.response.is_none() && self.is_expired(request))
+        {
+            Some(("sign request has timed out", true))
+        } else {
+            None
+        };
+
+        if let Some((message, expired)) = failure {
+            // Release every request of the batch that is still pending.
+            let mut refund = NearToken::from_yoctonear(0);
+            let mut predecessor_id = None;
+            for (request_id, request) in request_ids.iter().zip(requests) {
+                if let Some(request) = request {
+                    self.remove_pending_request(request_id);
+                    let request_id = *request_id;
+                    if expired {
+                        Event::SignExpired { request_id }.emit();
+                    } else {
+                        Event::SignCancelled { request_id }.emit();
+                    }
+                    refund = refund.saturating_add(request.deposit);
+                    predecessor_id = Some(request.predecessor_id);
+                }
+            }
+            return match predecessor_id {
+                Some(predecessor_id) => {
+                    PromiseOrValue::Promise(Self::refund_and_fail(predecessor_id, refund, message))
+                }
+                None => env::panic_str(message),
+            };
+        }
+
+        if requests
+            .iter()
+            .flatten()
+            .all(|request| request.response.is_some())
+        {
+            let mut refund = NearToken::from_yoctonear(0);
+            let mut signatures = Vec::with_capacity(requests.len());
+            let mut predecessor_id = None;
+            for (request_id, request) in request_ids.iter().zip(requests.into_iter().flatten()) {
+                self.remove_pending_request(request_id);
+                refund = refund.saturating_add(request.deposit.saturating_sub(request.fee));
+                signatures.push(request.response.unwrap());
+                predecessor_id = Some(request.predecessor_id);
             }
-            Some(_) => env::panic_str("Signature for this payload already requested"),
+            if let Some(predecessor_id) = predecessor_id.filter(|_| !refund.is_zero()) {
+                Promise::new(predecessor_id).transfer(refund);
+            }
+            return PromiseOrValue::Value(signatures);
         }
+
+        env::log_str(&format!("not ready yet (depth={})", depth));
+        let account_id = env::current_account_id();
+        PromiseOrValue::Promise(Self::ext(account_id).sign_batch_helper(request_ids, depth + 1))
     }
 
+    /// Polls a sign request made before the contract state was versioned. Only called by the
+    /// promises such requests left in flight, which still use the old name and arguments.
+    /// The migration could not carry these requests over, as they did not record who made
+    /// them, so they are removed and fail unless they were already responded to. They
+    /// carried no deposit, so there is nothing to refund.
     #[private]
+    #[allow(unused_variables)]
     pub fn sign_helper(
         &mut self,
         payload: [u8; 32],
         depth: usize,
     ) -> PromiseOrValue<(String, String)> {
-        if let Some(signature) = self.pending_requests.get(&payload) {
-            match signature {
-                Some(signature) => {
-                    self.pending_requests.remove(&payload);
-                    PromiseOrValue::Value(signature)
+        match legacy::v0::pending_requests().remove(&payload) {
+            Some(Some(signature)) => PromiseOrValue::Value(signature),
+            // Failing in a separate receipt keeps the removal from being rolled back.
+            Some(None) => PromiseOrValue::Promise(
+                Self::ext(env::current_account_id()).fail_helper(
+                    "sign request was dropped by a contract update, it has to be made again"
+                        .to_string(),
+                ),
+            ),
+            None => env::panic_str("unexpected request"),
+        }
+    }
+
+    #[private]
+    pub fn fail_helper(&mut self, message: String) {
+        env::panic_str(&message);
+    }
+
+    /// Cancels a pending sign request and refunds its deposit. Can only be called by the
+    /// account that made the request.
+    pub fn cancel_sign(&mut self, request_id: RequestId) {
+        let request = self
+            .pending_requests
+            .get(&request_id)
+            .unwrap_or_else(|| env::panic_str("unexpected request"));
+        if request.predecessor_id != env::predecessor_account_id() {
+            env::panic_str("only the account that made the request can cancel it");
+        }
+        if request.response.is_some() {
+            env::panic_str("sign request has already been responded to");
+        }
+        self.remove_pending_request(&request_id);
+        Event::SignCancelled { request_id }.emit();
+        if !request.deposit.is_zero() {
+            Promise::new(request.predecessor_id).transfer(request.deposit);
+        }
+    }
+
+    /// The deposit required to `join` as a candidate.
+    pub fn join_deposit(&self) -> NearToken {
+        self.config.join_deposit
+    }
+
+    /// The protocol fee charged for every sign request on top of its storage cost.
+    pub fn sign_fee(&self) -> NearToken {
+        self.config.sign_fee
+    }
+
+    /// Votes for a change to who may request signatures or how many requests they may have
+    /// pending. Applied once `threshold` participants have voted for it.
+    pub fn vote_sign_policy(&mut self, change: SignPolicyChange) -> bool {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                threshold,
+                sign_policy_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
                 }
-                None => {
-                    env::log_str(&format!("not ready yet (depth={})", depth));
-                    let account_id = env::current_account_id();
-                    PromiseOrValue::Promise(Self::ext(account_id).sign_helper(payload, depth + 1))
+                let voted = sign_policy_votes.entry(change.clone());
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    sign_policy_votes.remove(&change);
+                    Event::SignPolicyChanged {
+                        change: change.clone(),
+                    }
+                    .emit();
+                    self.sign_policy.apply(change);
+                    true
+                } else {
+                    false
                 }
             }
-        } else {
-            env::panic_str("unexpected request");
+            _ => env::panic_str("protocol state can't change the sign policy right now"),
         }
     }
 
-    pub fn respond(&mut self, payload: [u8; 32], big_r: String, s: String) {
-        self.pending_requests.insert(&payload, &Some((big_r, s)));
+    pub fn sign_policy(&self) -> SignPolicy {
+        self.sign_policy.clone()
+    }
+
+    /// Whether `account_id` may currently request a signature.
+    pub fn can_sign(&self, account_id: AccountId) -> bool {
+        if !self.sign_policy.allows(&account_id) {
+            return false;
+        }
+        match self.sign_policy.max_pending_requests {
+            Some(max) => self.pending_requests_of(account_id) < max,
+            None => true,
+        }
+    }
+
+    /// Number of pending requests made by `account_id`.
+    pub fn pending_requests_of(&self, account_id: AccountId) -> u64 {
+        self.predecessor_request_counts
+            .get(&account_id)
+            .unwrap_or(0)
+    }
+
+    pub fn vote_sign_fee(&mut self, fee: NearToken) -> bool {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                threshold,
+                fee_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                let voted = fee_votes.entry(fee);
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    self.config.sign_fee = fee;
+                    *fee_votes = FeeVotes::new();
+                    Event::ConfigChanged {
+                        config: self.config.clone(),
+                    }
+                    .emit();
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => env::panic_str("protocol state can't change the fee right now"),
+        }
+    }
+
+    /// Votes for the deposit candidates have to attach to `join`. Deposits already made are
+    /// not affected.
+    pub fn vote_join_deposit(&mut self, deposit: NearToken) -> bool {
+        self.require_not_paused();
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                threshold,
+                join_deposit_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                let voted = join_deposit_votes.entry(deposit);
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    self.config.join_deposit = deposit;
+                    *join_deposit_votes = FeeVotes::new();
+                    Event::ConfigChanged {
+                        config: self.config.clone(),
+                    }
+                    .emit();
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => env::panic_str("protocol state can't change the join deposit right now"),
+        }
+    }
+
+    /// Votes for the number of blocks a sign request can wait for a response before it
+    /// expires. Also applies to the requests that are already pending.
+    pub fn vote_request_timeout(&mut self, blocks: u64) -> bool {
+        self.require_not_paused();
+        if blocks == 0 {
+            env::panic_str("request timeout has to be at least one block");
+        }
+        match &mut self.protocol_state {
+            ProtocolContractState::Running(RunningContractState {
+                participants,
+                threshold,
+                request_timeout_votes,
+                ..
+            }) => {
+                let signer_account_id = env::signer_account_id();
+                if !participants.contains_key(&signer_account_id) {
+                    env::panic_str("calling account is not in the participant set");
+                }
+                let voted = request_timeout_votes.entry(blocks);
+                voted.insert(signer_account_id);
+                if voted.len() >= *threshold {
+                    self.config.request_timeout_blocks = blocks;
+                    *request_timeout_votes = TimeoutVotes::new();
+                    Event::ConfigChanged {
+                        config: self.config.clone(),
+                    }
+                    .emit();
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => env::panic_str("protocol state can't change the request timeout right now"),
+        }
+    }
+
+    /// Proposes a new version of the contract code. The attached deposit has to cover the
+    /// storage of the code; it is refunded once the update is deployed or withdrawn.
+    /// Unlike other votes, updates are accepted while the contract is paused.
+    #[payable]
+    pub fn propose_update(&mut self, code: Base64VecU8) -> CodeHash {
+        let ProtocolContractState::Running(state) = &self.protocol_state else {
+            env::panic_str("protocol state can't accept updates right now");
+        };
+        let signer_account_id = env::signer_account_id();
+        if !state.participants.contains_key(&signer_account_id) {
+            env::panic_str("calling account is not in the participant set");
+        }
+        let code: Vec<u8> = code.into();
+        let code_hash = env::sha256_array(&code);
+        if self.proposed_updates.get(&code_hash).is_some() {
+            env::panic_str("this update has already been proposed");
+        }
+        let deposit = env::attached_deposit();
+        let storage_before = env::storage_usage();
+        self.update_code.insert(&code_hash, &code);
+        self.proposed_updates.insert(
+            &code_hash,
+            &ProposedUpdate {
+                proposer: signer_account_id,
+                deposit,
+                votes: HashSet::new(),
+                proposed_at: env::block_height(),
+            },
+        );
+        let storage_used = env::storage_usage().saturating_sub(storage_before);
+        let required_deposit = env::storage_byte_cost().saturating_mul(storage_used as u128);
+        if deposit < required_deposit {
+            env::panic_str(&format!(
+                "attached deposit {deposit} is less than the required {required_deposit}"
+            ));
+        }
+        code_hash
+    }
+
+    /// Votes for a proposed update. Once `threshold` of the current participants have
+    /// voted for it, the code is deployed and `migrate` is called on the new version.
+    pub fn vote_update(&mut self, code_hash: CodeHash) -> bool {
+        let ProtocolContractState::Running(state) = &self.protocol_state else {
+            env::panic_str("protocol state can't accept updates right now");
+        };
+        let signer_account_id = env::signer_account_id();
+        if !state.participants.contains_key(&signer_account_id) {
+            env::panic_str("calling account is not in the participant set");
+        }
+        let mut update = self
+            .proposed_updates
+            .get(&code_hash)
+            .unwrap_or_else(|| env::panic_str("this update has not been proposed"));
+        if self.is_update_expired(&update) {
+            env::panic_str("this update has expired");
+        }
+        update.votes.insert(signer_account_id);
+        // Participants may have left since they voted.
+        let votes = update
+            .votes
+            .iter()
+            .filter(|voter| state.participants.contains_key(voter))
+            .count();
+        if votes < state.threshold {
+            self.proposed_updates.insert(&code_hash, &update);
+            return false;
+        }
+
+        self.proposed_updates.remove(&code_hash);
+        let code = self
+            .update_code
+            .remove(&code_hash)
+            .unwrap_or_else(|| env::panic_str("code of the update is missing"));
+        if !update.deposit.is_zero() {
+            Promise::new(update.proposer).transfer(update.deposit);
+        }
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(
+                "migrate".to_string(),
+                Vec::new(),
+                NearToken::from_yoctonear(0),
+                MIGRATE_GAS,
+            );
+        true
+    }
+
+    /// Withdraws a proposed update and refunds its deposit. The proposer can withdraw it at
+    /// any time, anyone else once it has been waiting for votes for longer than
+    /// `update_timeout_blocks`.
+    pub fn withdraw_update(&mut self, code_hash: CodeHash) {
+        let update = self
+            .proposed_updates
+            .get(&code_hash)
+            .unwrap_or_else(|| env::panic_str("this update has not been proposed"));
+        if env::signer_account_id() != update.proposer && !self.is_update_expired(&update) {
+            env::panic_str("only the proposer can withdraw an update before it expires");
+        }
+        self.proposed_updates.remove(&code_hash);
+        self.update_code.remove(&code_hash);
+        if !update.deposit.is_zero() {
+            Promise::new(update.proposer).transfer(update.deposit);
+        }
+    }
+
+    /// Submits the signature for a pending request. Only accepted from participants and only
+    /// if `signature` is a valid low-s signature of the request's payload under the key
+    /// derived for the request's predecessor and path, with the right recovery id.
+    pub fn respond(&mut self, request_id: RequestId, signature: SignatureResponse) {
+        let (participants, public_key) = match &self.protocol_state {
+            ProtocolContractState::Running(state) => (&state.participants, &state.public_key),
+            ProtocolContractState::Resharing(state) => (&state.old_participants, &state.public_key),
+            _ => env::panic_str("protocol is not running or resharing"),
+        };
+        let signer_account_id = env::signer_account_id();
+        if !participants.contains_key(&signer_account_id) {
+            env::panic_str("calling account is not in the participant set");
+        }
+        let mut request = self
+            .pending_requests
+            .get(&request_id)
+            .unwrap_or_else(|| env::panic_str("unexpected request"));
+
+        let root_public_key = crypto::near_public_key_to_affine_point(public_key.clone())
+            .unwrap_or_else(|| env::panic_str("root public key is not a valid secp256k1 key"));
+        let epsilon = crypto::derive_epsilon(&request.predecessor_id, &request.path);
+        let expected_public_key = crypto::derive_key(root_public_key, epsilon);
+        if !crypto::check_recoverable_signature(&expected_public_key, &signature, &request.payload)
+        {
+            env::panic_str("signature is not valid for the requested payload");
+        }
+
+        Event::SignResponded {
+            request_id,
+            responder: signer_account_id,
+            signature: signature.clone(),
+        }
+        .emit();
+        request.response = Some(signature);
+        self.pending_requests.insert(&request_id, &request);
     }
 
     #[private]
@@ -304,9 +1379,19 @@ impl MpcContract {
         for key in keys.iter() {
             env::storage_remove(&key.0);
         }
+        Self::write_state_version();
         Self {
             protocol_state: ProtocolContractState::NotInitialized,
-            pending_requests: LookupMap::new(b"m"),
+            pending_requests: LookupMap::new(b"r"),
+            pending_request_count: 0,
+            request_nonce: 0,
+            config: Config::default(),
+            proposed_updates: LookupMap::new(b"u"),
+            update_code: LookupMap::new(b"c"),
+            owner: None,
+            candidate_deposits: LookupMap::new(b"d"),
+            sign_policy: SignPolicy::default(),
+            predecessor_request_counts: LookupMap::new(b"p"),
         }
     }
 
@@ -318,4 +1403,267 @@ impl MpcContract {
             _ => env::panic_str("public key not available (protocol is not running or resharing)"),
         }
     }
+
+    /// The key that signatures requested by `predecessor` with `path` are made with. Derived
+    /// from the root public key the same way the nodes derive it.
+    pub fn derived_public_key(&self, predecessor: AccountId, path: String) -> DerivedPublicKey {
+        let root_public_key = crypto::near_public_key_to_affine_point(self.public_key())
+            .unwrap_or_else(|| env::panic_str("root public key is not a valid secp256k1 key"));
+        let epsilon = crypto::derive_epsilon(&predecessor, &path);
+        let derived_public_key = crypto::derive_key(root_public_key, epsilon);
+        DerivedPublicKey {
+            sec1: crypto::affine_point_to_sec1_hex(&derived_public_key),
+            near_public_key: crypto::affine_point_to_near_public_key(&derived_public_key),
+        }
+    }
+
+    /// Version of the contract code.
+    pub fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
+    /// Hash of the whole protocol state. Cheap to poll, so that callers only have to fetch
+    /// `state` once it changes.
+    pub fn state_hash(&self) -> [u8; 32] {
+        env::sha256_array(&borsh::to_vec(&self.protocol_state).unwrap())
+    }
+
+    pub fn epoch(&self) -> u64 {
+        match &self.protocol_state {
+            ProtocolContractState::Running(state) => state.epoch,
+            ProtocolContractState::Resharing(state) => state.old_epoch,
+            _ => env::panic_str("epoch not available (protocol is not running or resharing)"),
+        }
+    }
+
+    /// The threshold signatures are currently produced with. While resharing, this is the
+    /// threshold of the old participant set.
+    pub fn threshold(&self) -> usize {
+        match &self.protocol_state {
+            ProtocolContractState::Initializing(state) => state.threshold,
+            ProtocolContractState::Running(state) => state.threshold,
+            ProtocolContractState::Resharing(state) => state.old_threshold,
+            ProtocolContractState::NotInitialized => {
+                env::panic_str("threshold not available (protocol is not initialized)")
+            }
+        }
+    }
+
+    /// The participants currently producing signatures. While resharing, these are the old
+    /// participants.
+    pub fn participants(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<ParticipantInfo> {
+        let participants = match &self.protocol_state {
+            ProtocolContractState::Running(state) => &state.participants,
+            ProtocolContractState::Resharing(state) => &state.old_participants,
+            _ => {
+                env::panic_str("participants not available (protocol is not running or resharing)")
+            }
+        };
+        paginate(participants.iter(), from_index, limit)
+            .map(|(_, info)| info.clone())
+            .collect()
+    }
+
+    pub fn candidates(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<CandidateInfo> {
+        let candidates = match &self.protocol_state {
+            ProtocolContractState::Initializing(state) => &state.candidates,
+            ProtocolContractState::Running(state) => &state.candidates,
+            _ => return Vec::new(),
+        };
+        paginate(candidates.iter(), from_index, limit)
+            .map(|(_, info)| info.clone())
+            .collect()
+    }
+
+    /// The accounts that voted for each candidate to join.
+    pub fn join_votes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<VoteTally> {
+        match &self.protocol_state {
+            ProtocolContractState::Running(state) => state.join_votes.tallies(from_index, limit),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The accounts that voted for each participant to leave.
+    pub fn leave_votes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<VoteTally> {
+        match &self.protocol_state {
+            ProtocolContractState::Running(state) => state.leave_votes.tallies(from_index, limit),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn pending_request_count(&self) -> u64 {
+        self.pending_request_count
+    }
+
+    pub fn pending_request(&self, request_id: RequestId) -> Option<PendingRequest> {
+        self.pending_requests.get(&request_id)
+    }
+
+    pub fn config(&self) -> Config {
+        self.config.clone()
+    }
+}
+
+impl MpcContract {
+    fn require_not_paused(&self) {
+        if self.paused() {
+            env::panic_str("contract is paused");
+        }
+    }
+
+    fn require_owner(&self) {
+        if self.owner.as_ref() != Some(&env::predecessor_account_id()) {
+            env::panic_str("calling account is not the owner");
+        }
+    }
+
+    fn vote_paused(&mut self, paused: bool) -> bool {
+        let ProtocolContractState::Running(state) = &mut self.protocol_state else {
+            env::panic_str("can only pause or unpause while the protocol is running");
+        };
+        let signer_account_id = env::signer_account_id();
+        if !state.participants.contains_key(&signer_account_id) {
+            env::panic_str("calling account is not in the participant set");
+        }
+        if state.paused == paused {
+            env::panic_str("contract is already in the requested state");
+        }
+        let votes = if paused {
+            &mut state.pause_votes
+        } else {
+            &mut state.unpause_votes
+        };
+        votes.insert(signer_account_id);
+        if votes.len() >= state.threshold {
+            self.set_paused(paused);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        let ProtocolContractState::Running(state) = &mut self.protocol_state else {
+            env::panic_str("can only pause or unpause while the protocol is running");
+        };
+        state.paused = paused;
+        state.pause_votes.clear();
+        state.unpause_votes.clear();
+        Event::PauseChanged { paused }.emit();
+    }
+
+    fn remove_pending_request(&mut self, request_id: &RequestId) {
+        if let Some(request) = self.pending_requests.remove(request_id) {
+            self.pending_request_count -= 1;
+            let count = self.pending_requests_of(request.predecessor_id.clone());
+            if count > 1 {
+                self.predecessor_request_counts
+                    .insert(&request.predecessor_id, &(count - 1));
+            } else {
+                self.predecessor_request_counts
+                    .remove(&request.predecessor_id);
+            }
+        }
+    }
+
+    fn write_state_version() {
+        env::storage_write(STATE_VERSION_KEY, &borsh::to_vec(&STATE_VERSION).unwrap());
+    }
+
+    /// Adds a sign request for `payload` on behalf of the predecessor and announces it to
+    /// the nodes.
+    fn add_pending_request(
+        &mut self,
+        payload: [u8; 32],
+        path: String,
+        deposit: NearToken,
+        entropy: [u8; 32],
+        batch_id: Option<RequestId>,
+    ) -> RequestId {
+        let predecessor_id = env::predecessor_account_id();
+        if !self.sign_policy.allows(&predecessor_id) {
+            env::panic_str("predecessor is not allowed to request signatures");
+        }
+        let predecessor_count = self.pending_requests_of(predecessor_id.clone());
+        if let Some(max) = self.sign_policy.max_pending_requests {
+            if predecessor_count >= max {
+                env::panic_str(&format!(
+                    "predecessor already has the maximum of {max} pending requests"
+                ));
+            }
+        }
+        let request_id =
+            primitives::request_id(&predecessor_id, &path, &payload, self.request_nonce);
+        self.request_nonce += 1;
+        if self.pending_requests.get(&request_id).is_some() {
+            env::panic_str("Signature for this request already requested");
+        }
+        self.pending_requests.insert(
+            &request_id,
+            &PendingRequest {
+                payload,
+                predecessor_id: predecessor_id.clone(),
+                path: path.clone(),
+                deposit,
+                fee: self.config.sign_fee,
+                block_height: env::block_height(),
+                response: None,
+            },
+        );
+        self.pending_request_count += 1;
+        self.predecessor_request_counts
+            .insert(&predecessor_id, &(predecessor_count + 1));
+        Event::SignRequested {
+            request_id,
+            batch_id,
+            predecessor_id,
+            path,
+            payload,
+            entropy,
+        }
+        .emit();
+        request_id
+    }
+
+    /// Checks that `deposit` covers the storage used since `storage_before` plus the fee of
+    /// `requests` sign requests.
+    fn require_sign_deposit(
+        &self,
+        deposit: NearToken,
+        storage_before: StorageUsage,
+        requests: u128,
+    ) {
+        let storage_used = env::storage_usage().saturating_sub(storage_before);
+        let required_deposit = env::storage_byte_cost()
+            .saturating_mul(storage_used as u128)
+            .saturating_add(self.config.sign_fee.saturating_mul(requests));
+        if deposit < required_deposit {
+            env::panic_str(&format!(
+                "attached deposit {deposit} is less than the required {required_deposit}"
+            ));
+        }
+    }
+
+    fn is_expired(&self, request: &PendingRequest) -> bool {
+        env::block_height() > request.block_height + self.config.request_timeout_blocks
+    }
+
+    fn is_update_expired(&self, update: &ProposedUpdate) -> bool {
+        env::block_height() > update.proposed_at + self.config.update_timeout_blocks
+    }
+
+    /// Refunds `amount` to `account_id` and then fails with `message`, so that the caller of
+    /// `sign` sees an error while the refund still goes through.
+    fn refund_and_fail(account_id: AccountId, amount: NearToken, message: &str) -> Promise {
+        let fail = Self::ext(env::current_account_id()).fail_helper(message.to_string());
+        if amount.is_zero() {
+            fail
+        } else {
+            Promise::new(account_id).transfer(amount).then(fail)
+        }
+    }
 }
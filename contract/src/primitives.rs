@@ -1,12 +1,16 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{AccountId, PublicKey};
-use std::collections::{BTreeMap, HashSet};
+use near_sdk::{env, AccountId, BlockHeight, NearToken, PublicKey};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 pub mod hpke {
     pub type PublicKey = [u8; 32];
 }
 
+/// Numeric id of a participant, used by the nodes to address each other. Assigned when the
+/// participant is admitted and never changed or reused afterwards.
+pub type ParticipantId = u32;
+
 #[derive(
     Serialize,
     Deserialize,
@@ -21,6 +25,7 @@ pub mod hpke {
     Debug,
 )]
 pub struct ParticipantInfo {
+    pub id: ParticipantId,
     pub account_id: AccountId,
     pub url: String,
     /// The public key used for encrypting messages.
@@ -29,9 +34,10 @@ pub struct ParticipantInfo {
     pub sign_pk: PublicKey,
 }
 
-impl From<CandidateInfo> for ParticipantInfo {
-    fn from(candidate_info: CandidateInfo) -> Self {
+impl ParticipantInfo {
+    pub fn from_candidate(id: ParticipantId, candidate_info: CandidateInfo) -> Self {
         ParticipantInfo {
+            id,
             account_id: candidate_info.account_id,
             url: candidate_info.url,
             cipher_pk: candidate_info.cipher_pk,
@@ -65,6 +71,8 @@ pub struct CandidateInfo {
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone)]
 pub struct Participants {
     pub participants: BTreeMap<AccountId, ParticipantInfo>,
+    /// The id the next admitted participant gets.
+    pub next_id: ParticipantId,
 }
 
 impl Default for Participants {
@@ -73,11 +81,13 @@ impl Default for Participants {
     }
 }
 
+/// The initial candidates are numbered in the order of their account ids, which is also the
+/// order the nodes number them in while generating the key.
 impl From<Candidates> for Participants {
     fn from(candidates: Candidates) -> Self {
         let mut participants = Participants::new();
-        for (account_id, candidate_info) in candidates.iter() {
-            participants.insert(account_id.clone(), candidate_info.clone().into());
+        for (_, candidate_info) in candidates.iter() {
+            participants.admit(candidate_info.clone());
         }
         participants
     }
@@ -87,6 +97,7 @@ impl Participants {
     pub fn new() -> Self {
         Participants {
             participants: BTreeMap::new(),
+            next_id: 0,
         }
     }
 
@@ -94,6 +105,16 @@ impl Participants {
         self.participants.contains_key(account_id)
     }
 
+    /// Adds the candidate as a participant with a new id.
+    pub fn admit(&mut self, candidate_info: CandidateInfo) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert(
+            candidate_info.account_id.clone(),
+            ParticipantInfo::from_candidate(id, candidate_info),
+        );
+    }
+
     pub fn insert(&mut self, account_id: AccountId, participant_info: ParticipantInfo) {
         self.participants.insert(account_id, participant_info);
     }
@@ -183,6 +204,34 @@ impl Votes {
     pub fn entry(&mut self, account_id: AccountId) -> &mut HashSet<AccountId> {
         self.votes.entry(account_id).or_default()
     }
+
+    /// Removes the vote `voter` cast concerning `account_id`. Returns whether there was one.
+    pub fn remove_vote(&mut self, account_id: &AccountId, voter: &AccountId) -> bool {
+        let Some(voters) = self.votes.get_mut(account_id) else {
+            return false;
+        };
+        let removed = voters.remove(voter);
+        if voters.is_empty() {
+            self.votes.remove(account_id);
+        }
+        removed
+    }
+
+    pub fn tallies(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<VoteTally> {
+        paginate(self.votes.iter(), from_index, limit)
+            .map(|(account_id, voters)| VoteTally {
+                account_id: account_id.clone(),
+                voters: voters.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+/// The accounts that voted for something concerning `account_id`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteTally {
+    pub account_id: AccountId,
+    pub voters: Vec<AccountId>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
@@ -207,3 +256,296 @@ impl PkVotes {
         self.votes.entry(public_key).or_default()
     }
 }
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
+pub struct FeeVotes {
+    pub votes: BTreeMap<NearToken, HashSet<AccountId>>,
+}
+
+impl Default for FeeVotes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeeVotes {
+    pub fn new() -> Self {
+        FeeVotes {
+            votes: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(&mut self, fee: NearToken) -> &mut HashSet<AccountId> {
+        self.votes.entry(fee).or_default()
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
+pub struct ThresholdVotes {
+    pub votes: BTreeMap<usize, HashSet<AccountId>>,
+}
+
+impl Default for ThresholdVotes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThresholdVotes {
+    pub fn new() -> Self {
+        ThresholdVotes {
+            votes: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(&mut self, threshold: usize) -> &mut HashSet<AccountId> {
+        self.votes.entry(threshold).or_default()
+    }
+}
+
+/// Votes for a number of blocks, e.g. a timeout.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug)]
+pub struct TimeoutVotes {
+    pub votes: BTreeMap<u64, HashSet<AccountId>>,
+}
+
+impl Default for TimeoutVotes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeoutVotes {
+    pub fn new() -> Self {
+        TimeoutVotes {
+            votes: BTreeMap::new(),
+        }
+    }
+
+    pub fn entry(&mut self, blocks: u64) -> &mut HashSet<AccountId> {
+        self.votes.entry(blocks).or_default()
+    }
+}
+
+/// Who may request signatures and how many requests they may have pending at once.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SignPolicy {
+    /// If not empty, only these accounts may request signatures.
+    pub allowlist: BTreeSet<AccountId>,
+    /// Accounts that may not request signatures, even if they are on the allowlist.
+    pub denylist: BTreeSet<AccountId>,
+    /// The most requests a single predecessor may have pending at once, if limited.
+    pub max_pending_requests: Option<u64>,
+}
+
+impl SignPolicy {
+    pub fn allows(&self, account_id: &AccountId) -> bool {
+        !self.denylist.contains(account_id)
+            && (self.allowlist.is_empty() || self.allowlist.contains(account_id))
+    }
+
+    pub fn apply(&mut self, change: SignPolicyChange) {
+        match change {
+            SignPolicyChange::Allow(account_id) => {
+                self.allowlist.insert(account_id);
+            }
+            SignPolicyChange::Disallow(account_id) => {
+                self.allowlist.remove(&account_id);
+            }
+            SignPolicyChange::Deny(account_id) => {
+                self.denylist.insert(account_id);
+            }
+            SignPolicyChange::Undeny(account_id) => {
+                self.denylist.remove(&account_id);
+            }
+            SignPolicyChange::SetMaxPendingRequests(max_pending_requests) => {
+                self.max_pending_requests = max_pending_requests;
+            }
+        }
+    }
+}
+
+/// A change to the [`SignPolicy`] that participants can vote for.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SignPolicyChange {
+    /// Adds the account to the allowlist.
+    Allow(AccountId),
+    /// Removes the account from the allowlist.
+    Disallow(AccountId),
+    /// Adds the account to the denylist.
+    Deny(AccountId),
+    /// Removes the account from the denylist.
+    Undeny(AccountId),
+    SetMaxPendingRequests(Option<u64>),
+}
+
+/// Votes for changes to the [`SignPolicy`]. Kept as a list since the changes can't be used as
+/// JSON object keys.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Default)]
+pub struct SignPolicyVotes {
+    pub votes: Vec<(SignPolicyChange, HashSet<AccountId>)>,
+}
+
+impl SignPolicyVotes {
+    pub fn new() -> Self {
+        SignPolicyVotes { votes: Vec::new() }
+    }
+
+    pub fn entry(&mut self, change: SignPolicyChange) -> &mut HashSet<AccountId> {
+        let index = match self.votes.iter().position(|(voted, _)| *voted == change) {
+            Some(index) => index,
+            None => {
+                self.votes.push((change, HashSet::new()));
+                self.votes.len() - 1
+            }
+        };
+        &mut self.votes[index].1
+    }
+
+    pub fn remove(&mut self, change: &SignPolicyChange) {
+        self.votes.retain(|(voted, _)| voted != change);
+    }
+}
+
+/// Number of items returned by paginated views when no limit is given.
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+
+/// Skips `from_index` items and takes at most `limit` of the rest.
+pub fn paginate<I: Iterator>(
+    iter: I,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+) -> std::iter::Take<std::iter::Skip<I>> {
+    iter.skip(from_index.unwrap_or(0) as usize)
+        .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+}
+
+/// Unique identifier of a sign request, see [`request_id`].
+pub type RequestId = [u8; 32];
+
+/// Derives the id of a sign request. The nonce makes sure that the same payload can be
+/// requested several times by the same account and path.
+pub fn request_id(
+    predecessor_id: &AccountId,
+    path: &str,
+    payload: &[u8; 32],
+    nonce: u64,
+) -> RequestId {
+    let bytes = borsh::to_vec(&(predecessor_id, path, payload, nonce)).unwrap();
+    env::sha256_array(&bytes)
+}
+
+/// A sign request that is waiting for the participants to respond.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone)]
+pub struct PendingRequest {
+    /// The payload to be signed.
+    pub payload: [u8; 32],
+    /// The account that requested the signature. Used to derive the signing key.
+    pub predecessor_id: AccountId,
+    /// The derivation path requested by `predecessor_id`.
+    pub path: String,
+    /// The deposit attached to the request. Everything except `fee` is refunded once the
+    /// request is responded to, and all of it if the request is cancelled or expires.
+    pub deposit: NearToken,
+    /// The protocol fee at the time the request was made.
+    pub fee: NearToken,
+    /// The block height at which the request was made.
+    pub block_height: BlockHeight,
+    /// The verified response, if any.
+    pub response: Option<SignatureResponse>,
+}
+
+/// How `sign_message` hashes a message into the digest that gets signed.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum HashScheme {
+    /// Keccak-256, as used by EVM chains.
+    Keccak256,
+    Sha256,
+    /// SHA-256 applied twice, as used by Bitcoin.
+    DoubleSha256,
+}
+
+impl HashScheme {
+    pub fn digest(&self, message: &[u8]) -> [u8; 32] {
+        match self {
+            HashScheme::Keccak256 => env::keccak256_array(message),
+            HashScheme::Sha256 => env::sha256_array(message),
+            HashScheme::DoubleSha256 => env::sha256_array(&env::sha256_array(message)),
+        }
+    }
+}
+
+/// A secp256k1 ECDSA signature along with its recovery id, so that chains recovering the
+/// signer from the signature (e.g. EVM or Bitcoin) can use it as is.
+///
+/// The nodes read the `payload` of a request as a little-endian integer, so the digest that
+/// is signed is the payload with its bytes reversed. To sign a digest that another chain
+/// will verify, pass it to `sign` reversed, or use `sign_message` which does that itself.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignatureResponse {
+    /// The x coordinate of `R`, big-endian.
+    pub r: [u8; 32],
+    /// Big-endian, always in the lower half of the curve order.
+    pub s: [u8; 32],
+    /// The recovery id: 0 if the y coordinate of `R` is even and 1 if it is odd. EVM expects
+    /// 27 to be added to it.
+    pub v: u8,
+}
+
+/// Contract-wide settings.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Number of blocks a sign request can wait for a response before it expires.
+    pub request_timeout_blocks: u64,
+    /// Protocol fee charged for every sign request on top of its storage cost.
+    pub sign_fee: NearToken,
+    /// Number of blocks after which a stuck resharing can be aborted by any old participant.
+    pub resharing_timeout_blocks: u64,
+    /// Whether participants need the approval of the others to update their url and keys.
+    pub participant_update_requires_vote: bool,
+    /// Deposit required to `join` as a candidate. Refunded once the resharing the candidate
+    /// is voted into finishes or is cancelled, or when the candidate withdraws.
+    pub join_deposit: NearToken,
+    /// Number of blocks a proposed update can wait for votes before anyone can withdraw it.
+    pub update_timeout_blocks: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            request_timeout_blocks: 200,
+            sign_fee: NearToken::from_yoctonear(0),
+            resharing_timeout_blocks: 3600,
+            participant_update_requires_vote: false,
+            join_deposit: NearToken::from_near(1),
+            update_timeout_blocks: 7 * 24 * 3600,
+        }
+    }
+}
+
+/// A key derived from the root public key for a predecessor and path.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DerivedPublicKey {
+    /// Hex encoding of the compressed SEC1 representation of the key.
+    pub sec1: String,
+    /// The key in NEAR's `secp256k1:...` format.
+    pub near_public_key: PublicKey,
+}
+
+/// Hash of a proposed contract code update.
+pub type CodeHash = [u8; 32];
+
+/// A contract code update proposed by one of the participants. The code itself is stored
+/// separately so that voting does not have to read and write it.
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct ProposedUpdate {
+    pub proposer: AccountId,
+    /// Covers the storage of the code and is refunded to the proposer once the update
+    /// is deployed.
+    pub deposit: NearToken,
+    pub votes: HashSet<AccountId>,
+    /// The block height at which the update was proposed.
+    pub proposed_at: BlockHeight,
+}
@@ -0,0 +1,1328 @@
+use super::*;
+use k256::ecdsa::SigningKey;
+use k256::{ProjectivePoint, Scalar};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::{testing_env, CurveType};
+
+const THRESHOLD: usize = 2;
+const PARTICIPANTS: usize = 3;
+
+fn contract_id() -> AccountId {
+    "mpc.test".parse().unwrap()
+}
+
+/// Sets up the environment for a call made by `caller` at `block_height`.
+fn call(caller: &AccountId, deposit: NearToken, block_height: u64) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(contract_id())
+        .signer_account_id(caller.clone())
+        .predecessor_account_id(caller.clone())
+        .attached_deposit(deposit)
+        .block_height(block_height)
+        .build());
+}
+
+fn candidate(account_id: AccountId) -> CandidateInfo {
+    CandidateInfo {
+        account_id,
+        url: "http://localhost".to_string(),
+        cipher_pk: [0; 32],
+        sign_pk: PublicKey::from_parts(CurveType::ED25519, vec![0; 32]).unwrap(),
+    }
+}
+
+fn root_secret_key() -> Scalar {
+    Scalar::from(7u64)
+}
+
+/// A contract running with the first `PARTICIPANTS` test accounts as participants.
+fn running_contract() -> MpcContract {
+    running_contract_with(PARTICIPANTS, THRESHOLD)
+}
+
+/// A contract running with the first `participants` test accounts as participants.
+fn running_contract_with(participants: usize, threshold: usize) -> MpcContract {
+    let candidates = (0..participants)
+        .map(|i| (accounts(i), candidate(accounts(i))))
+        .collect();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    let mut contract = MpcContract::init(threshold, candidates, None, None);
+    let root_public_key = (ProjectivePoint::GENERATOR * root_secret_key()).to_affine();
+    let public_key = crypto::affine_point_to_near_public_key(&root_public_key);
+    for i in 0..threshold {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        contract.vote_pk(public_key.clone());
+    }
+    assert!(matches!(
+        contract.protocol_state,
+        ProtocolContractState::Running(_)
+    ));
+    contract
+}
+
+/// Signs `payload` the way the nodes would for a request made by `predecessor` with `path`.
+fn sign_payload(predecessor: &AccountId, path: &str, payload: [u8; 32]) -> SignatureResponse {
+    let epsilon = crypto::derive_epsilon(predecessor, path);
+    let signing_key = SigningKey::from_bytes(&(root_secret_key() + epsilon).to_bytes()).unwrap();
+    // The contract reads payloads as little-endian integers, ECDSA reads them as big-endian.
+    let mut prehash = payload;
+    prehash.reverse();
+    let (mut signature, recovery_id) = signing_key.sign_prehash_recoverable(&prehash).unwrap();
+    let mut v = recovery_id.to_byte();
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+        v ^= 1;
+    }
+    SignatureResponse {
+        r: signature.r().to_bytes().into(),
+        s: signature.s().to_bytes().into(),
+        v,
+    }
+}
+
+/// Requests a signature of `payload` as `user` and returns the request id.
+fn request(contract: &mut MpcContract, user: &AccountId, payload: [u8; 32]) -> RequestId {
+    let nonce = contract.request_nonce;
+    call(user, NearToken::from_near(1), 0);
+    contract.sign(payload, "path".to_string());
+    primitives::request_id(user, "path", &payload, nonce)
+}
+
+#[test]
+fn respond_accepts_a_valid_signature() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    let signature = sign_payload(&user, "path", [1; 32]);
+
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    contract.respond(request_id, signature.clone());
+    let pending = contract.pending_request(request_id).unwrap();
+    assert_eq!(pending.response, Some(signature.clone()));
+
+    match contract.sign_request_helper(request_id, 0) {
+        PromiseOrValue::Value(returned) => assert_eq!(returned, signature),
+        PromiseOrValue::Promise(_) => panic!("the response should be returned"),
+    }
+    assert!(contract.pending_request(request_id).is_none());
+    assert_eq!(contract.pending_request_count(), 0);
+    assert_eq!(contract.pending_requests_of(user), 0);
+}
+
+#[test]
+#[should_panic(expected = "signature is not valid for the requested payload")]
+fn respond_rejects_a_signature_of_another_payload() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    let signature = sign_payload(&user, "path", [2; 32]);
+
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    contract.respond(request_id, signature);
+}
+
+#[test]
+#[should_panic(expected = "signature is not valid for the requested payload")]
+fn respond_rejects_a_wrong_recovery_id() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    let mut signature = sign_payload(&user, "path", [1; 32]);
+    signature.v ^= 1;
+
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    contract.respond(request_id, signature);
+}
+
+#[test]
+#[should_panic(expected = "calling account is not in the participant set")]
+fn respond_rejects_non_participants() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    let signature = sign_payload(&user, "path", [1; 32]);
+
+    call(&user, NearToken::from_yoctonear(0), 1);
+    contract.respond(request_id, signature);
+}
+
+#[test]
+fn cancel_sign_removes_the_request() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    assert_eq!(contract.pending_request_count(), 1);
+
+    call(&user, NearToken::from_yoctonear(0), 1);
+    contract.cancel_sign(request_id);
+    assert!(contract.pending_request(request_id).is_none());
+    assert_eq!(contract.pending_request_count(), 0);
+    assert_eq!(contract.pending_requests_of(user), 0);
+}
+
+#[test]
+#[should_panic(expected = "only the account that made the request can cancel it")]
+fn cancel_sign_is_restricted_to_the_predecessor() {
+    let mut contract = running_contract();
+    let request_id = request(&mut contract, &accounts(5), [1; 32]);
+
+    call(&accounts(4), NearToken::from_yoctonear(0), 1);
+    contract.cancel_sign(request_id);
+}
+
+#[test]
+fn expired_requests_are_removed() {
+    let mut contract = running_contract();
+    let request_id = request(&mut contract, &accounts(5), [1; 32]);
+    let timeout = contract.config().request_timeout_blocks;
+
+    call(&contract_id(), NearToken::from_yoctonear(0), timeout);
+    assert!(matches!(
+        contract.sign_request_helper(request_id, 1),
+        PromiseOrValue::Promise(_)
+    ));
+    assert!(contract.pending_request(request_id).is_some());
+
+    call(&contract_id(), NearToken::from_yoctonear(0), timeout + 1);
+    assert!(matches!(
+        contract.sign_request_helper(request_id, 2),
+        PromiseOrValue::Promise(_)
+    ));
+    assert!(contract.pending_request(request_id).is_none());
+    assert_eq!(contract.pending_request_count(), 0);
+}
+
+#[test]
+fn voted_request_timeout_applies_to_pending_requests() {
+    let mut contract = running_contract();
+    let request_id = request(&mut contract, &accounts(5), [1; 32]);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        assert_eq!(contract.vote_request_timeout(10), i + 1 == THRESHOLD);
+    }
+    assert_eq!(contract.config().request_timeout_blocks, 10);
+
+    call(&contract_id(), NearToken::from_yoctonear(0), 11);
+    assert!(matches!(
+        contract.sign_request_helper(request_id, 1),
+        PromiseOrValue::Promise(_)
+    ));
+    assert!(contract.pending_request(request_id).is_none());
+}
+
+#[test]
+#[should_panic(expected = "calling account is not in the participant set")]
+fn request_timeout_votes_are_restricted_to_participants() {
+    let mut contract = running_contract();
+    call(&accounts(5), NearToken::from_yoctonear(0), 0);
+    contract.vote_request_timeout(10);
+}
+
+#[test]
+fn voted_fee_is_charged_to_new_requests() {
+    let mut contract = running_contract();
+    let fee = NearToken::from_millinear(10);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        assert_eq!(contract.vote_sign_fee(fee), i + 1 == THRESHOLD);
+    }
+    assert_eq!(contract.sign_fee(), fee);
+
+    let request_id = request(&mut contract, &accounts(5), [1; 32]);
+    let pending = contract.pending_request(request_id).unwrap();
+    assert_eq!(pending.fee, fee);
+    assert_eq!(pending.deposit, NearToken::from_near(1));
+}
+
+#[test]
+#[should_panic(expected = "is less than the required")]
+fn sign_requires_a_deposit_covering_the_fee() {
+    let mut contract = running_contract();
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        contract.vote_sign_fee(NearToken::from_near(2));
+    }
+    request(&mut contract, &accounts(5), [1; 32]);
+}
+
+#[test]
+fn threshold_votes_start_a_resharing() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    assert!(!contract.vote_threshold(3));
+    call(&accounts(1), NearToken::from_yoctonear(0), 0);
+    assert!(contract.vote_threshold(3));
+    match contract.protocol_state {
+        ProtocolContractState::Resharing(state) => {
+            assert_eq!(state.old_threshold, THRESHOLD);
+            assert_eq!(state.threshold, 3);
+            assert_eq!(state.new_participants.len(), PARTICIPANTS);
+        }
+        _ => panic!("the contract should be resharing"),
+    }
+}
+
+#[test]
+#[should_panic(expected = "threshold is larger than the number of participants")]
+fn threshold_votes_reject_more_than_the_participants() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    contract.vote_threshold(PARTICIPANTS + 1);
+}
+
+#[test]
+#[should_panic(expected = "threshold is too low")]
+fn threshold_votes_reject_too_low_thresholds() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    contract.vote_threshold(MIN_THRESHOLD - 1);
+}
+
+#[test]
+#[should_panic(expected = "threshold is too low")]
+fn threshold_votes_reject_a_minority_threshold() {
+    let mut contract = running_contract_with(5, 3);
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    contract.vote_threshold(2);
+}
+
+#[test]
+fn min_threshold_is_a_majority() {
+    assert_eq!(min_threshold(1), MIN_THRESHOLD);
+    assert_eq!(min_threshold(3), 2);
+    assert_eq!(min_threshold(4), 3);
+    assert_eq!(min_threshold(5), 3);
+    assert_eq!(min_threshold(6), 4);
+}
+
+#[test]
+#[should_panic(expected = "threshold is too low")]
+fn init_rejects_a_threshold_below_a_majority() {
+    let candidates = (0..4)
+        .map(|i| (accounts(i), candidate(accounts(i))))
+        .collect();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    MpcContract::init(2, candidates, None, None);
+}
+
+#[test]
+#[should_panic(expected = "threshold is larger than the number of candidates")]
+fn init_rejects_a_threshold_above_the_candidates() {
+    let candidates = (0..3)
+        .map(|i| (accounts(i), candidate(accounts(i))))
+        .collect();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    MpcContract::init(4, candidates, None, None);
+}
+
+#[test]
+fn joins_raise_the_threshold_to_a_majority() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    call(&joiner, contract.join_deposit(), 0);
+    contract.join(
+        "http://localhost".to_string(),
+        [0; 32],
+        candidate(joiner.clone()).sign_pk,
+    );
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        contract.vote_join(joiner.clone());
+    }
+    assert!(logged_events().contains(&Event::ThresholdRaised {
+        old_threshold: THRESHOLD,
+        threshold: min_threshold(PARTICIPANTS + 1),
+    }));
+    match contract.protocol_state {
+        ProtocolContractState::Resharing(state) => {
+            assert_eq!(state.new_participants.len(), PARTICIPANTS + 1);
+            assert_eq!(state.old_threshold, THRESHOLD);
+            assert_eq!(state.threshold, min_threshold(PARTICIPANTS + 1));
+        }
+        _ => panic!("the contract should be resharing"),
+    }
+}
+
+#[test]
+fn sign_batch_adds_a_request_per_item() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    call(&user, NearToken::from_near(1), 0);
+    contract.sign_batch(vec![
+        ([1; 32], "a".to_string()),
+        ([2; 32], "b".to_string()),
+        ([3; 32], "c".to_string()),
+    ]);
+    assert_eq!(contract.pending_request_count(), 3);
+    assert_eq!(contract.pending_requests_of(user.clone()), 3);
+
+    // The deposit is split between the requests, the first one getting the remainder.
+    let deposits: Vec<_> = [([1; 32], "a"), ([2; 32], "b"), ([3; 32], "c")]
+        .iter()
+        .enumerate()
+        .map(|(nonce, (payload, path))| {
+            let request_id = primitives::request_id(&user, path, payload, nonce as u64);
+            contract.pending_request(request_id).unwrap().deposit
+        })
+        .collect();
+    let share = NearToken::from_near(1).as_yoctonear() / 3;
+    assert_eq!(
+        deposits,
+        vec![
+            NearToken::from_yoctonear(share + NearToken::from_near(1).as_yoctonear() % 3),
+            NearToken::from_yoctonear(share),
+            NearToken::from_yoctonear(share),
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "batch is empty")]
+fn sign_batch_rejects_empty_batches() {
+    let mut contract = running_contract();
+    call(&accounts(5), NearToken::from_near(1), 0);
+    contract.sign_batch(Vec::new());
+}
+
+#[test]
+#[should_panic(expected = "batch is larger than the maximum")]
+fn sign_batch_rejects_oversized_batches() {
+    let mut contract = running_contract();
+    call(&accounts(5), NearToken::from_near(1), 0);
+    contract.sign_batch(vec![([1; 32], "a".to_string()); MAX_BATCH_SIZE + 1]);
+}
+
+#[test]
+fn voted_updates_are_deployed() {
+    let mut contract = running_contract();
+    let code = vec![1u8; 100];
+    call(&accounts(0), NearToken::from_near(1), 0);
+    let code_hash = contract.propose_update(code.clone().into());
+    assert_eq!(code_hash, env::sha256_array(&code));
+
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    assert!(!contract.vote_update(code_hash));
+    call(&accounts(2), NearToken::from_yoctonear(0), 1);
+    assert!(contract.vote_update(code_hash));
+    assert!(contract.proposed_updates.get(&code_hash).is_none());
+    assert!(contract.update_code.get(&code_hash).is_none());
+    assert!(near_sdk::test_utils::get_created_receipts()
+        .iter()
+        .any(|receipt| receipt.receiver_id == contract_id()));
+}
+
+#[test]
+fn updates_are_accepted_while_paused() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    assert!(!contract.vote_pause());
+    call(&accounts(1), NearToken::from_yoctonear(0), 0);
+    assert!(contract.vote_pause());
+    assert!(contract.paused());
+
+    call(&accounts(0), NearToken::from_near(1), 1);
+    let code_hash = contract.propose_update(vec![1u8; 100].into());
+    call(&accounts(1), NearToken::from_yoctonear(0), 2);
+    assert!(!contract.vote_update(code_hash));
+    call(&accounts(2), NearToken::from_yoctonear(0), 2);
+    assert!(contract.vote_update(code_hash));
+}
+
+#[test]
+#[should_panic(expected = "can only pause or unpause while the protocol is running")]
+fn pause_votes_are_rejected_while_resharing() {
+    let mut contract = running_contract();
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        contract.vote_threshold(3);
+    }
+    call(&accounts(0), NearToken::from_yoctonear(0), 0);
+    contract.vote_pause();
+}
+
+#[test]
+#[should_panic(expected = "this update has expired")]
+fn expired_updates_can_not_be_voted_for() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_near(1), 0);
+    let code_hash = contract.propose_update(vec![1u8; 100].into());
+
+    let timeout = contract.config().update_timeout_blocks;
+    call(&accounts(1), NearToken::from_yoctonear(0), timeout + 1);
+    contract.vote_update(code_hash);
+}
+
+#[test]
+fn updates_can_be_withdrawn() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_near(1), 0);
+    let code_hash = contract.propose_update(vec![1u8; 100].into());
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.withdraw_update(code_hash);
+    assert!(contract.proposed_updates.get(&code_hash).is_none());
+    assert!(contract.update_code.get(&code_hash).is_none());
+
+    // Anyone can withdraw an update once it has expired.
+    call(&accounts(0), NearToken::from_near(1), 2);
+    let code_hash = contract.propose_update(vec![2u8; 100].into());
+    let timeout = contract.config().update_timeout_blocks;
+    call(&accounts(5), NearToken::from_yoctonear(0), 2 + timeout + 1);
+    contract.withdraw_update(code_hash);
+    assert!(contract.proposed_updates.get(&code_hash).is_none());
+}
+
+#[test]
+#[should_panic(expected = "only the proposer can withdraw an update before it expires")]
+fn updates_can_only_be_withdrawn_by_the_proposer() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_near(1), 0);
+    let code_hash = contract.propose_update(vec![1u8; 100].into());
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    contract.withdraw_update(code_hash);
+}
+
+/// Writes the state a contract deployed before versioning would have left behind, with a
+/// responded and an unresponded request.
+/// The test accounts with the given indices as participants in the v0 layout.
+fn v0_participants(indices: std::ops::Range<usize>) -> legacy::v0::Participants {
+    legacy::v0::Participants {
+        participants: indices
+            .map(|i| {
+                let candidate = candidate(accounts(i));
+                (
+                    accounts(i),
+                    legacy::v0::ParticipantInfo {
+                        account_id: candidate.account_id,
+                        url: candidate.url,
+                        cipher_pk: candidate.cipher_pk,
+                        sign_pk: candidate.sign_pk,
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
+fn write_v0_state() {
+    let participants = v0_participants(0..PARTICIPANTS);
+    let root_public_key = (ProjectivePoint::GENERATOR * root_secret_key()).to_affine();
+    let mut pending_requests = legacy::v0::pending_requests();
+    pending_requests.insert(&[1; 32], &Some(("big_r".to_string(), "s".to_string())));
+    pending_requests.insert(&[2; 32], &None);
+    env::state_write(&legacy::v0::MpcContract {
+        protocol_state: legacy::v0::ProtocolContractState::Running(
+            legacy::v0::RunningContractState {
+                epoch: 3,
+                participants,
+                threshold: THRESHOLD,
+                public_key: crypto::affine_point_to_near_public_key(&root_public_key),
+                candidates: Candidates::new(),
+                join_votes: Votes::new(),
+                leave_votes: Votes::new(),
+            },
+        ),
+        pending_requests,
+    });
+}
+
+#[test]
+fn migrate_converts_the_v0_state() {
+    call(&contract_id(), NearToken::from_yoctonear(0), 0);
+    write_v0_state();
+    let mut contract = MpcContract::migrate();
+    match &contract.protocol_state {
+        ProtocolContractState::Running(state) => {
+            assert_eq!(state.epoch, 3);
+            assert_eq!(state.threshold, THRESHOLD);
+            let ids: Vec<_> = state.participants.iter().map(|(_, info)| info.id).collect();
+            assert_eq!(ids, (0..PARTICIPANTS as u32).collect::<Vec<_>>());
+            assert_eq!(state.participants.next_id, PARTICIPANTS as u32);
+        }
+        _ => panic!("the contract should be running"),
+    }
+    assert_eq!(
+        env::storage_read(STATE_VERSION_KEY),
+        Some(borsh::to_vec(&STATE_VERSION).unwrap())
+    );
+
+    // Requests left in flight by the old version can still collect their response, the
+    // others fail. Either way they are removed.
+    match contract.sign_helper([1; 32], 5) {
+        PromiseOrValue::Value(signature) => {
+            assert_eq!(signature, ("big_r".to_string(), "s".to_string()))
+        }
+        PromiseOrValue::Promise(_) => panic!("the response should be returned"),
+    }
+    assert!(matches!(
+        contract.sign_helper([2; 32], 5),
+        PromiseOrValue::Promise(_)
+    ));
+    assert!(legacy::v0::pending_requests().get(&[1; 32]).is_none());
+    assert!(legacy::v0::pending_requests().get(&[2; 32]).is_none());
+}
+
+#[test]
+fn migrate_numbers_both_sets_of_a_v0_resharing_alike() {
+    call(&contract_id(), NearToken::from_yoctonear(0), 0);
+    let root_public_key = (ProjectivePoint::GENERATOR * root_secret_key()).to_affine();
+    // The last participant is leaving while an account sorting before all of them joins.
+    env::state_write(&legacy::v0::MpcContract {
+        protocol_state: legacy::v0::ProtocolContractState::Resharing(
+            legacy::v0::ResharingContractState {
+                old_epoch: 3,
+                old_participants: v0_participants(1..PARTICIPANTS + 1),
+                new_participants: v0_participants(0..PARTICIPANTS),
+                threshold: THRESHOLD,
+                public_key: crypto::affine_point_to_near_public_key(&root_public_key),
+                finished_votes: HashSet::new(),
+            },
+        ),
+        pending_requests: legacy::v0::pending_requests(),
+    });
+    let contract = MpcContract::migrate();
+    match &contract.protocol_state {
+        ProtocolContractState::Resharing(state) => {
+            let id =
+                |participants: &Participants, i: usize| participants.get(&accounts(i)).unwrap().id;
+            // The old participants keep the ids the nodes numbered them with.
+            for i in 1..PARTICIPANTS + 1 {
+                assert_eq!(id(&state.old_participants, i), (i - 1) as u32);
+            }
+            for i in 1..PARTICIPANTS {
+                assert_eq!(
+                    id(&state.new_participants, i),
+                    id(&state.old_participants, i)
+                );
+            }
+            assert_eq!(id(&state.new_participants, 0), PARTICIPANTS as u32);
+            assert_eq!(state.old_participants.next_id, PARTICIPANTS as u32 + 1);
+            assert_eq!(state.new_participants.next_id, PARTICIPANTS as u32 + 1);
+        }
+        _ => panic!("the contract should be resharing"),
+    }
+}
+
+/// Starts a resharing at `block_height` that removes the last participant.
+fn start_resharing(contract: &mut MpcContract, block_height: u64) {
+    let leaving = accounts(PARTICIPANTS - 1);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), block_height);
+        contract.vote_leave(leaving.clone());
+    }
+    assert!(matches!(
+        contract.protocol_state,
+        ProtocolContractState::Resharing(_)
+    ));
+}
+
+#[test]
+fn cancelled_resharing_rolls_back_to_the_old_epoch() {
+    let mut contract = running_contract();
+    start_resharing(&mut contract, 10);
+    assert!(!contract.resharing_expired());
+
+    call(&accounts(0), NearToken::from_yoctonear(0), 11);
+    assert!(!contract.vote_cancel_resharing(0));
+    call(&accounts(1), NearToken::from_yoctonear(0), 11);
+    assert!(contract.vote_cancel_resharing(0));
+    match &contract.protocol_state {
+        ProtocolContractState::Running(state) => {
+            assert_eq!(state.epoch, 0);
+            assert_eq!(state.threshold, THRESHOLD);
+            assert_eq!(state.participants.len(), PARTICIPANTS);
+        }
+        _ => panic!("the contract should be running"),
+    }
+    // Late votes for the cancelled resharing are accepted.
+    call(&accounts(2), NearToken::from_yoctonear(0), 12);
+    assert!(contract.vote_cancel_resharing(0));
+}
+
+#[test]
+fn expired_resharing_is_cancelled_by_a_single_vote() {
+    let mut contract = running_contract();
+    start_resharing(&mut contract, 10);
+    let timeout = contract.config().resharing_timeout_blocks;
+
+    call(&accounts(2), NearToken::from_yoctonear(0), 10 + timeout);
+    assert!(!contract.resharing_expired());
+    call(&accounts(2), NearToken::from_yoctonear(0), 10 + timeout + 1);
+    assert!(contract.resharing_expired());
+    assert!(contract.vote_cancel_resharing(0));
+    assert_eq!(contract.epoch(), 0);
+    assert!(!contract.resharing_expired());
+}
+
+#[test]
+#[should_panic(expected = "calling account is not in the old participant set")]
+fn resharing_can_only_be_cancelled_by_old_participants() {
+    let mut contract = running_contract();
+    start_resharing(&mut contract, 10);
+    call(&accounts(5), NearToken::from_yoctonear(0), 11);
+    contract.vote_cancel_resharing(0);
+}
+
+#[test]
+#[should_panic(expected = "mismatched epochs")]
+fn resharing_cancel_votes_have_to_match_the_epoch() {
+    let mut contract = running_contract();
+    start_resharing(&mut contract, 10);
+    call(&accounts(0), NearToken::from_yoctonear(0), 11);
+    contract.vote_cancel_resharing(1);
+}
+
+/// The url the participant `account_id` is currently reachable at.
+fn participant_url(contract: &MpcContract, account_id: &AccountId) -> String {
+    contract
+        .participants(None, None)
+        .into_iter()
+        .find(|info| info.account_id == *account_id)
+        .unwrap()
+        .url
+}
+
+#[test]
+fn participant_info_updates_apply_right_away() {
+    let mut contract = running_contract();
+    let sign_pk = candidate(accounts(1)).sign_pk;
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    assert!(contract.update_participant_info("http://new".to_string(), [1; 32], sign_pk));
+
+    let participants = contract.participants(None, None);
+    let updated = participants
+        .iter()
+        .find(|info| info.account_id == accounts(1))
+        .unwrap();
+    assert_eq!(updated.url, "http://new");
+    assert_eq!(updated.cipher_pk, [1; 32]);
+    assert_eq!(updated.id, 1);
+}
+
+#[test]
+fn participant_info_updates_can_require_votes() {
+    let mut contract = running_contract();
+    contract.config.participant_update_requires_vote = true;
+    let sign_pk = candidate(accounts(1)).sign_pk;
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    assert!(!contract.update_participant_info("http://new".to_string(), [1; 32], sign_pk));
+    assert_eq!(participant_url(&contract, &accounts(1)), "http://localhost");
+
+    call(&accounts(0), NearToken::from_yoctonear(0), 2);
+    assert!(!contract.vote_participant_info(accounts(1)));
+    call(&accounts(2), NearToken::from_yoctonear(0), 2);
+    assert!(contract.vote_participant_info(accounts(1)));
+    assert_eq!(participant_url(&contract, &accounts(1)), "http://new");
+}
+
+#[test]
+#[should_panic(expected = "participant has not proposed an update")]
+fn participant_info_votes_need_a_proposed_update() {
+    let mut contract = running_contract();
+    contract.config.participant_update_requires_vote = true;
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.vote_participant_info(accounts(1));
+}
+
+#[test]
+#[should_panic(expected = "calling account is not in the participant set")]
+fn participant_info_can_only_be_updated_by_participants() {
+    let mut contract = running_contract();
+    let sign_pk = candidate(accounts(5)).sign_pk;
+    call(&accounts(5), NearToken::from_yoctonear(0), 1);
+    contract.update_participant_info("http://new".to_string(), [1; 32], sign_pk);
+}
+
+#[test]
+fn events_can_be_parsed_back_from_logs() {
+    let participant = ParticipantInfo::from_candidate(0, candidate(accounts(0)));
+    let events = vec![
+        Event::SignRequested {
+            request_id: [1; 32],
+            batch_id: Some([2; 32]),
+            predecessor_id: accounts(5),
+            path: "path".to_string(),
+            payload: [3; 32],
+            entropy: [4; 32],
+        },
+        Event::MessageHashed {
+            request_id: [1; 32],
+            scheme: HashScheme::Keccak256,
+            digest: [5; 32],
+        },
+        Event::SignResponded {
+            request_id: [1; 32],
+            responder: accounts(0),
+            signature: sign_payload(&accounts(5), "path", [3; 32]),
+        },
+        Event::SignCancelled {
+            request_id: [1; 32],
+        },
+        Event::SignExpired {
+            request_id: [1; 32],
+        },
+        Event::JoinVoted {
+            voter: accounts(0),
+            candidate: accounts(3),
+        },
+        Event::LeaveVoted {
+            voter: accounts(0),
+            account_id: accounts(2),
+        },
+        Event::ThresholdVoted {
+            voter: accounts(0),
+            threshold: 3,
+        },
+        Event::ParticipantInfoProposed {
+            participant: participant.clone(),
+        },
+        Event::ParticipantInfoUpdated { participant },
+        Event::ConfigChanged {
+            config: Config::default(),
+        },
+        Event::SignPolicyChanged {
+            change: SignPolicyChange::SetMaxPendingRequests(Some(3)),
+        },
+        Event::PkVoted {
+            voter: accounts(0),
+            public_key: candidate(accounts(0)).sign_pk,
+        },
+        Event::ResharingStarted {
+            old_epoch: 0,
+            new_participants: vec![accounts(0), accounts(1)],
+            threshold: 2,
+        },
+        Event::ResharingFinished { epoch: 1 },
+        Event::ResharingCancelled { epoch: 0 },
+        Event::EpochChanged { epoch: 1 },
+        Event::PauseChanged { paused: true },
+    ];
+    call(&contract_id(), NearToken::from_yoctonear(0), 0);
+    for event in events.clone() {
+        event.emit();
+    }
+    let logs = near_sdk::test_utils::get_logs();
+    assert!(logs
+        .iter()
+        .all(|log| log.starts_with(r#"EVENT_JSON:{"standard":"mpc","version":"1.0.0","event":"#)));
+    let parsed: Vec<_> = logs.iter().filter_map(|log| Event::from_log(log)).collect();
+    assert_eq!(parsed, events);
+}
+
+#[test]
+fn from_log_ignores_other_logs() {
+    assert_eq!(Event::from_log("not ready yet (depth=0)"), None);
+    assert_eq!(
+        Event::from_log(
+            r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"epoch_changed","data":{"epoch":1}}"#
+        ),
+        None
+    );
+}
+
+/// The events logged since the last call was set up.
+fn logged_events() -> Vec<Event> {
+    near_sdk::test_utils::get_logs()
+        .iter()
+        .filter_map(|log| Event::from_log(log))
+        .collect()
+}
+
+#[test]
+fn cancelled_and_expired_requests_are_announced() {
+    let mut contract = running_contract();
+    let cancelled = request(&mut contract, &accounts(5), [1; 32]);
+    let expired = request(&mut contract, &accounts(5), [2; 32]);
+
+    call(&accounts(5), NearToken::from_yoctonear(0), 1);
+    contract.cancel_sign(cancelled);
+    assert_eq!(
+        logged_events(),
+        vec![Event::SignCancelled {
+            request_id: cancelled
+        }]
+    );
+
+    let timeout = contract.config().request_timeout_blocks;
+    call(&contract_id(), NearToken::from_yoctonear(0), timeout + 1);
+    assert!(matches!(
+        contract.sign_request_helper(expired, 1),
+        PromiseOrValue::Promise(_)
+    ));
+    assert_eq!(
+        logged_events(),
+        vec![Event::SignExpired {
+            request_id: expired
+        }]
+    );
+}
+
+#[test]
+fn participants_are_paginated() {
+    let contract = running_contract();
+    let ids = |from_index, limit| -> Vec<_> {
+        contract
+            .participants(from_index, limit)
+            .into_iter()
+            .map(|info| info.id)
+            .collect()
+    };
+    assert_eq!(ids(None, None), vec![0, 1, 2]);
+    assert_eq!(ids(Some(1), None), vec![1, 2]);
+    assert_eq!(ids(Some(1), Some(1)), vec![1]);
+    assert_eq!(ids(None, Some(0)), Vec::<u32>::new());
+    assert_eq!(ids(Some(PARTICIPANTS as u64), None), Vec::<u32>::new());
+    assert_eq!(ids(Some(u64::MAX), Some(u64::MAX)), Vec::<u32>::new());
+}
+
+#[test]
+fn candidates_and_join_votes_are_paginated() {
+    let mut contract = running_contract();
+    for i in PARTICIPANTS..PARTICIPANTS + 2 {
+        call(&accounts(i), contract.join_deposit(), 0);
+        contract.join(
+            "http://localhost".to_string(),
+            [0; 32],
+            candidate(accounts(i)).sign_pk,
+        );
+        call(&accounts(0), NearToken::from_yoctonear(0), 0);
+        assert!(!contract.vote_join(accounts(i)));
+    }
+
+    let candidates: Vec<_> = contract
+        .candidates(Some(1), Some(5))
+        .into_iter()
+        .map(|info| info.account_id)
+        .collect();
+    assert_eq!(candidates, vec![accounts(PARTICIPANTS + 1)]);
+    assert!(contract.candidates(Some(2), None).is_empty());
+    assert!(contract.candidates(None, Some(0)).is_empty());
+
+    let votes = contract.join_votes(None, Some(1));
+    assert_eq!(votes.len(), 1);
+    assert_eq!(votes[0].account_id, accounts(PARTICIPANTS));
+    assert_eq!(votes[0].voters, vec![accounts(0)]);
+    assert!(contract.join_votes(Some(2), None).is_empty());
+    assert!(contract.leave_votes(None, None).is_empty());
+}
+
+#[test]
+fn pending_requests_can_be_viewed() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let request_id = request(&mut contract, &user, [1; 32]);
+    let pending = contract.pending_request(request_id).unwrap();
+    assert_eq!(pending.payload, [1; 32]);
+    assert_eq!(pending.predecessor_id, user);
+    assert_eq!(pending.path, "path");
+    assert_eq!(pending.response, None);
+    assert_eq!(contract.pending_request_count(), 1);
+    assert!(contract.pending_request([0; 32]).is_none());
+}
+
+/// Derived with the node's `kdf::derive_key` from the root key of `running_contract`, see
+/// the node's `kdf` tests which check the same vector.
+const DERIVED_KEY_SEC1: &str = "0277ae2f5720b80f255fab3c07f137179f9153ef03b6de775d0395b4d8fab7059d";
+
+#[test]
+fn derived_public_key_matches_the_nodes() {
+    let contract = running_contract();
+    let derived = contract.derived_public_key(
+        "alice.near".parse().unwrap(),
+        "m/44'/60'/0'/0/0".to_string(),
+    );
+    assert_eq!(derived.sec1, DERIVED_KEY_SEC1);
+    let point = crypto::near_public_key_to_affine_point(derived.near_public_key).unwrap();
+    assert_eq!(crypto::affine_point_to_sec1_hex(&point), DERIVED_KEY_SEC1);
+}
+
+/// Registers `account_id` as a candidate with `deposit` attached.
+fn join(contract: &mut MpcContract, account_id: &AccountId, deposit: NearToken) {
+    call(account_id, deposit, 0);
+    contract.join(
+        "http://localhost".to_string(),
+        [0; 32],
+        candidate(account_id.clone()).sign_pk,
+    );
+}
+
+/// Whether a transfer to `account_id` has been scheduled since the last call was set up.
+fn refunded(account_id: &AccountId) -> bool {
+    near_sdk::test_utils::get_created_receipts()
+        .iter()
+        .any(|receipt| receipt.receiver_id == *account_id)
+}
+
+#[test]
+#[should_panic(expected = "is less than the required")]
+fn join_requires_the_deposit() {
+    let mut contract = running_contract();
+    let deposit = contract.join_deposit();
+    join(
+        &mut contract,
+        &accounts(PARTICIPANTS),
+        deposit.saturating_sub(NearToken::from_yoctonear(1)),
+    );
+}
+
+#[test]
+fn withdrawn_candidates_are_refunded() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.vote_join(joiner.clone());
+
+    call(&joiner, NearToken::from_yoctonear(0), 2);
+    contract.withdraw_candidacy();
+    assert!(refunded(&joiner));
+    assert!(contract.candidates(None, None).is_empty());
+    assert!(contract.join_votes(None, None).is_empty());
+    assert!(contract.candidate_deposits.get(&joiner).is_none());
+}
+
+#[test]
+fn votes_can_be_revoked() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    assert!(!contract.vote_join(joiner.clone()));
+    contract.revoke_vote(joiner.clone());
+    assert!(contract.join_votes(None, None).is_empty());
+
+    // With the first vote revoked, a second one is not enough.
+    call(&accounts(1), NearToken::from_yoctonear(0), 1);
+    assert!(!contract.vote_join(joiner));
+
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    assert!(!contract.vote_leave(accounts(2)));
+    contract.revoke_vote(accounts(2));
+    assert!(contract.leave_votes(None, None).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "calling account has not voted for this account")]
+fn only_cast_votes_can_be_revoked() {
+    let mut contract = running_contract();
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.revoke_vote(accounts(2));
+}
+
+#[test]
+fn join_deposits_are_held_until_the_resharing_finishes() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        contract.vote_join(joiner.clone());
+        assert!(!refunded(&joiner));
+    }
+    assert_eq!(contract.candidate_deposits.get(&joiner), Some(deposit));
+
+    call(&accounts(0), NearToken::from_yoctonear(0), 2);
+    assert!(!contract.vote_reshared(1));
+    assert!(!refunded(&joiner));
+    call(&accounts(1), NearToken::from_yoctonear(0), 2);
+    assert!(contract.vote_reshared(1));
+    assert!(refunded(&joiner));
+    assert!(contract.candidate_deposits.get(&joiner).is_none());
+}
+
+#[test]
+fn join_deposits_are_refunded_when_the_resharing_is_cancelled() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        contract.vote_join(joiner.clone());
+    }
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 2);
+        contract.vote_cancel_resharing(0);
+    }
+    assert_eq!(contract.epoch(), 0);
+    assert!(refunded(&joiner));
+    assert!(contract.candidate_deposits.get(&joiner).is_none());
+}
+
+#[test]
+#[should_panic(expected = "candidate is being reshared into the participant set")]
+fn join_deposits_can_not_be_withdrawn_while_resharing() {
+    let mut contract = running_contract();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        contract.vote_join(joiner.clone());
+    }
+    call(&joiner, NearToken::from_yoctonear(0), 2);
+    contract.withdraw_candidacy();
+}
+
+#[test]
+fn voted_join_deposit_applies_to_new_candidates() {
+    let mut contract = running_contract();
+    let deposit = NearToken::from_near(5);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        assert_eq!(contract.vote_join_deposit(deposit), i + 1 == THRESHOLD);
+    }
+    assert_eq!(contract.join_deposit(), deposit);
+    assert_eq!(contract.config().join_deposit, deposit);
+}
+
+/// Applies `change` to the sign policy with the votes of `THRESHOLD` participants.
+fn vote_sign_policy(contract: &mut MpcContract, change: SignPolicyChange) {
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 0);
+        assert_eq!(
+            contract.vote_sign_policy(change.clone()),
+            i + 1 == THRESHOLD
+        );
+    }
+}
+
+#[test]
+fn allowlist_restricts_who_can_sign() {
+    let mut contract = running_contract();
+    assert!(contract.can_sign(accounts(4)));
+    vote_sign_policy(&mut contract, SignPolicyChange::Allow(accounts(5)));
+    assert!(contract.can_sign(accounts(5)));
+    assert!(!contract.can_sign(accounts(4)));
+    request(&mut contract, &accounts(5), [1; 32]);
+
+    vote_sign_policy(&mut contract, SignPolicyChange::Disallow(accounts(5)));
+    assert!(contract.sign_policy().allowlist.is_empty());
+    assert!(contract.can_sign(accounts(4)));
+}
+
+#[test]
+#[should_panic(expected = "predecessor is not allowed to request signatures")]
+fn accounts_off_the_allowlist_can_not_sign() {
+    let mut contract = running_contract();
+    vote_sign_policy(&mut contract, SignPolicyChange::Allow(accounts(5)));
+    request(&mut contract, &accounts(4), [1; 32]);
+}
+
+#[test]
+#[should_panic(expected = "predecessor is not allowed to request signatures")]
+fn denied_accounts_can_not_sign() {
+    let mut contract = running_contract();
+    vote_sign_policy(&mut contract, SignPolicyChange::Allow(accounts(5)));
+    vote_sign_policy(&mut contract, SignPolicyChange::Deny(accounts(5)));
+    assert!(!contract.can_sign(accounts(5)));
+    request(&mut contract, &accounts(5), [1; 32]);
+}
+
+#[test]
+fn undenied_accounts_can_sign_again() {
+    let mut contract = running_contract();
+    vote_sign_policy(&mut contract, SignPolicyChange::Deny(accounts(5)));
+    assert!(!contract.can_sign(accounts(5)));
+    vote_sign_policy(&mut contract, SignPolicyChange::Undeny(accounts(5)));
+    assert!(contract.can_sign(accounts(5)));
+    request(&mut contract, &accounts(5), [1; 32]);
+}
+
+#[test]
+#[should_panic(expected = "predecessor already has the maximum of 2 pending requests")]
+fn pending_requests_are_limited() {
+    let mut contract = running_contract();
+    vote_sign_policy(
+        &mut contract,
+        SignPolicyChange::SetMaxPendingRequests(Some(2)),
+    );
+    request(&mut contract, &accounts(5), [1; 32]);
+    request(&mut contract, &accounts(5), [2; 32]);
+    assert!(!contract.can_sign(accounts(5)));
+    assert!(contract.can_sign(accounts(4)));
+    request(&mut contract, &accounts(5), [3; 32]);
+}
+
+#[test]
+fn pending_request_quota_is_released() {
+    let mut contract = running_contract();
+    vote_sign_policy(
+        &mut contract,
+        SignPolicyChange::SetMaxPendingRequests(Some(3)),
+    );
+    let user = accounts(5);
+    let responded = request(&mut contract, &user, [1; 32]);
+    let cancelled = request(&mut contract, &user, [2; 32]);
+    let expired = request(&mut contract, &user, [3; 32]);
+    assert_eq!(contract.pending_requests_of(user.clone()), 3);
+    assert!(!contract.can_sign(user.clone()));
+
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.respond(responded, sign_payload(&user, "path", [1; 32]));
+    assert_eq!(contract.pending_requests_of(user.clone()), 3);
+    call(&contract_id(), NearToken::from_yoctonear(0), 1);
+    assert!(matches!(
+        contract.sign_request_helper(responded, 1),
+        PromiseOrValue::Value(_)
+    ));
+    assert_eq!(contract.pending_requests_of(user.clone()), 2);
+    assert!(contract.can_sign(user.clone()));
+
+    call(&user, NearToken::from_yoctonear(0), 1);
+    contract.cancel_sign(cancelled);
+    assert_eq!(contract.pending_requests_of(user.clone()), 1);
+
+    let timeout = contract.config().request_timeout_blocks;
+    call(&contract_id(), NearToken::from_yoctonear(0), timeout + 1);
+    assert!(matches!(
+        contract.sign_request_helper(expired, 1),
+        PromiseOrValue::Promise(_)
+    ));
+    assert_eq!(contract.pending_requests_of(user), 0);
+}
+
+fn bytes_from_hex(hex: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    bytes
+}
+
+/// Digests of "hello world" under each scheme, in the byte order of the hash function.
+const HELLO_WORLD_DIGESTS: [(HashScheme, &str); 3] = [
+    (
+        HashScheme::Keccak256,
+        "47173285a8d7341e5e972fc677286384f802f8ef42a5ec5f03bbfa254cb01fad",
+    ),
+    (
+        HashScheme::Sha256,
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+    ),
+    (
+        HashScheme::DoubleSha256,
+        "bc62d4b80d9e36da29c16c5d4d9f11731f36052c72401a76c23c0fb5a9b74423",
+    ),
+];
+
+/// Requests a signature of "hello world" hashed with `scheme` as `user` and returns the
+/// request id.
+fn request_message(contract: &mut MpcContract, user: &AccountId, scheme: HashScheme) -> RequestId {
+    let nonce = contract.request_nonce;
+    call(user, NearToken::from_near(1), 0);
+    contract.sign_message(b"hello world".to_vec().into(), scheme, "path".to_string());
+    let mut payload = scheme.digest(b"hello world");
+    payload.reverse();
+    primitives::request_id(user, "path", &payload, nonce)
+}
+
+#[test]
+fn sign_message_requests_the_reversed_digest() {
+    let mut contract = running_contract();
+    let user = accounts(5);
+    for (scheme, digest) in HELLO_WORLD_DIGESTS {
+        let digest = bytes_from_hex(digest);
+        let request_id = request_message(&mut contract, &user, scheme);
+        let mut payload = digest;
+        payload.reverse();
+        assert_eq!(
+            contract.pending_request(request_id).unwrap().payload,
+            payload
+        );
+        assert!(logged_events().contains(&Event::MessageHashed {
+            request_id,
+            scheme,
+            digest,
+        }));
+    }
+}
+
+#[test]
+fn sign_message_signatures_verify_against_the_digest() {
+    use k256::ecdsa::signature::hazmat::PrehashVerifier;
+    use k256::ecdsa::{Signature, VerifyingKey};
+
+    let mut contract = running_contract();
+    let user = accounts(5);
+    let (scheme, digest) = HELLO_WORLD_DIGESTS[0];
+    let digest = bytes_from_hex(digest);
+    let request_id = request_message(&mut contract, &user, scheme);
+    let payload = contract.pending_request(request_id).unwrap().payload;
+    let signature = sign_payload(&user, "path", payload);
+    call(&accounts(0), NearToken::from_yoctonear(0), 1);
+    contract.respond(request_id, signature.clone());
+
+    // What another chain would do with the signature: verify it against the digest of the
+    // message under the derived key.
+    let derived = contract.derived_public_key(user, "path".to_string());
+    let derived = crypto::near_public_key_to_affine_point(derived.near_public_key).unwrap();
+    let verifying_key = VerifyingKey::from_affine(derived).unwrap();
+    let signature = Signature::from_scalars(signature.r, signature.s).unwrap();
+    verifying_key.verify_prehash(&digest, &signature).unwrap();
+}
+
+/// The ids of the current participants, in the order of their account ids.
+fn participant_ids(contract: &MpcContract) -> Vec<u32> {
+    contract
+        .participants(None, None)
+        .into_iter()
+        .map(|info| info.id)
+        .collect()
+}
+
+#[test]
+fn ids_of_cancelled_joiners_are_not_reused() {
+    let mut contract = running_contract();
+    let first = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &first, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        contract.vote_join(first.clone());
+    }
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 2);
+        contract.vote_cancel_resharing(0);
+    }
+
+    let second = accounts(PARTICIPANTS + 1);
+    join(&mut contract, &second, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 3);
+        contract.vote_join(second.clone());
+    }
+    match &contract.protocol_state {
+        ProtocolContractState::Resharing(state) => {
+            assert_eq!(
+                state.new_participants.get(&second).unwrap().id,
+                PARTICIPANTS as u32 + 1
+            );
+        }
+        _ => panic!("the contract should be resharing"),
+    }
+}
+
+#[test]
+fn admitted_participants_get_ids_after_the_migrated_ones() {
+    call(&contract_id(), NearToken::from_yoctonear(0), 0);
+    write_v0_state();
+    let mut contract = MpcContract::migrate();
+    let joiner = accounts(PARTICIPANTS);
+    let deposit = contract.join_deposit();
+    join(&mut contract, &joiner, deposit);
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 1);
+        contract.vote_join(joiner.clone());
+    }
+    for i in 0..THRESHOLD {
+        call(&accounts(i), NearToken::from_yoctonear(0), 2);
+        contract.vote_reshared(4);
+    }
+    assert_eq!(
+        participant_ids(&contract),
+        (0..PARTICIPANTS as u32 + 1).collect::<Vec<_>>()
+    );
+}
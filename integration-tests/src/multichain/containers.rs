@@ -1,5 +1,6 @@
 use ed25519_dalek::ed25519::signature::digest::{consts::U32, generic_array::GenericArray};
 use mpc_keys::hpke;
+use mpc_recovery_node::protocol::DEFAULT_PROPOSER_FAILOVER_BLOCKS;
 use multi_party_eddsa::protocols::ExpandedKeyPair;
 use near_workspaces::AccountId;
 use testcontainers::{
@@ -52,10 +53,13 @@ impl<'a> Node<'a> {
                 start_block_height: 0,
             },
             my_address: None,
+            proposer_failover_blocks: DEFAULT_PROPOSER_FAILOVER_BLOCKS,
             storage_options: mpc_recovery_node::storage::Options {
                 gcp_project_id: None,
                 sk_share_secret_id: None,
+                stockpile_dir: None,
             },
+            stockpile_options: Default::default(),
         }
         .into_str_args();
         let image: GenericImage = GenericImage::new("near/mpc-recovery-node", "latest")
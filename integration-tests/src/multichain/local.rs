@@ -1,6 +1,7 @@
 use crate::{mpc, util};
 use async_process::Child;
 use mpc_keys::hpke;
+use mpc_recovery_node::protocol::DEFAULT_PROPOSER_FAILOVER_BLOCKS;
 use near_workspaces::AccountId;
 
 #[allow(dead_code)]
@@ -39,10 +40,13 @@ impl Node {
                 start_block_height: 0,
             },
             my_address: None,
+            proposer_failover_blocks: DEFAULT_PROPOSER_FAILOVER_BLOCKS,
             storage_options: mpc_recovery_node::storage::Options {
                 gcp_project_id: None,
                 sk_share_secret_id: None,
+                stockpile_dir: None,
             },
+            stockpile_options: Default::default(),
         };
 
         let mpc_node_id = format!("multichain/{account_id}", account_id = account_id);
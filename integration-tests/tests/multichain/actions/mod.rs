@@ -2,12 +2,12 @@ pub mod wait_for;
 
 use crate::MultichainTestContext;
 
-use cait_sith::FullSignature;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
 use k256::elliptic_curve::sec1::FromEncodedPoint;
-use k256::{AffinePoint, EncodedPoint, Scalar, Secp256k1};
+use k256::{AffinePoint, EncodedPoint};
+use mpc_contract::primitives::SignatureResponse;
 use mpc_contract::RunningContractState;
 use mpc_recovery_node::kdf;
-use mpc_recovery_node::util::ScalarExt;
 use near_crypto::InMemorySigner;
 use near_jsonrpc_client::methods::broadcast_tx_async::RpcBroadcastTxAsyncRequest;
 use near_lake_primitives::CryptoHash;
@@ -48,7 +48,8 @@ pub async fn request_sign(
                         "path": "test",
                     }))?,
                     gas: 300_000_000_000_000,
-                    deposit: 0,
+                    // Covers the storage of the request, the rest is refunded.
+                    deposit: 10_000_000_000_000_000_000_000,
                 })],
             }
             .sign(&signer),
@@ -62,16 +63,26 @@ pub async fn assert_signature(
     account_id: &near_workspaces::AccountId,
     pk_bytes: &[u8],
     payload: &[u8; 32],
-    signature: &FullSignature<Secp256k1>,
+    signature: &SignatureResponse,
 ) {
     let point = EncodedPoint::from_bytes(pk_bytes).unwrap();
     let public_key = AffinePoint::from_encoded_point(&point).unwrap();
     let epsilon = kdf::derive_epsilon(account_id, "test");
 
-    assert!(signature.verify(
-        &kdf::derive_key(public_key, epsilon),
-        &Scalar::from_bytes(payload),
-    ));
+    // The nodes read the payload as a little-endian scalar, while ECDSA prehashes are
+    // big-endian.
+    let mut prehash = *payload;
+    prehash.reverse();
+    let recovered_key = VerifyingKey::recover_from_prehash(
+        &prehash,
+        &Signature::from_scalars(signature.r, signature.s).unwrap(),
+        RecoveryId::from_byte(signature.v).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        recovered_key.as_affine(),
+        &kdf::derive_key(public_key, epsilon)
+    );
 }
 
 pub async fn single_signature_production(
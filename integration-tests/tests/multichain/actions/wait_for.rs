@@ -2,10 +2,7 @@ use crate::MultichainTestContext;
 use anyhow::Context;
 use backon::ExponentialBuilder;
 use backon::Retryable;
-use cait_sith::FullSignature;
-use k256::AffinePoint;
-use k256::Scalar;
-use k256::Secp256k1;
+use mpc_contract::primitives::SignatureResponse;
 use mpc_contract::ProtocolContractState;
 use mpc_contract::RunningContractState;
 use mpc_recovery_node::web::StateView;
@@ -115,7 +112,7 @@ pub async fn has_at_least_presignatures<'a>(
 pub async fn signature_responded(
     ctx: &MultichainTestContext<'_>,
     tx_hash: CryptoHash,
-) -> anyhow::Result<FullSignature<Secp256k1>> {
+) -> anyhow::Result<SignatureResponse> {
     let is_tx_ready = || async {
         let outcome_view = ctx
             .jsonrpc_client
@@ -129,8 +126,7 @@ pub async fn signature_responded(
         let FinalExecutionStatus::SuccessValue(payload) = outcome_view.status else {
             anyhow::bail!("tx finished unsuccessfully: {:?}", outcome_view.status);
         };
-        let (big_r, s): (AffinePoint, Scalar) = serde_json::from_slice(&payload)?;
-        let signature = cait_sith::FullSignature::<Secp256k1> { big_r, s };
+        let signature: SignatureResponse = serde_json::from_slice(&payload)?;
         Ok(signature)
     };
 
@@ -1,4 +1,4 @@
-use crate::protocol::{MpcSignProtocol, SignQueue};
+use crate::protocol::{stockpile, MpcSignProtocol, SignQueue, DEFAULT_PROPOSER_FAILOVER_BLOCKS};
 use crate::{indexer, storage, web};
 use clap::Parser;
 use local_ip_address::local_ip;
@@ -47,9 +47,20 @@ pub enum Cli {
         /// Local address that other peers can use to message this node.
         #[arg(long, env("MPC_RECOVERY_LOCAL_ADDRESS"))]
         my_address: Option<Url>,
+        /// Number of blocks after which the next proposer of a sign request takes over from
+        /// one that has not served it.
+        #[arg(
+            long,
+            env("MPC_RECOVERY_PROPOSER_FAILOVER_BLOCKS"),
+            default_value_t = DEFAULT_PROPOSER_FAILOVER_BLOCKS
+        )]
+        proposer_failover_blocks: u64,
         /// Storage options
         #[clap(flatten)]
         storage_options: storage::Options,
+        /// Triple and presignature stockpile options
+        #[clap(flatten)]
+        stockpile_options: stockpile::Options,
     },
 }
 
@@ -66,7 +77,9 @@ impl Cli {
                 cipher_sk,
                 indexer_options,
                 my_address,
+                proposer_failover_blocks,
                 storage_options,
+                stockpile_options,
             } => {
                 let mut args = vec![
                     "start".to_string(),
@@ -84,12 +97,15 @@ impl Cli {
                     cipher_pk,
                     "--cipher-sk".to_string(),
                     cipher_sk,
+                    "--proposer-failover-blocks".to_string(),
+                    proposer_failover_blocks.to_string(),
                 ];
                 if let Some(my_address) = my_address {
                     args.extend(vec!["--my-address".to_string(), my_address.to_string()]);
                 }
                 args.extend(indexer_options.into_str_args());
                 args.extend(storage_options.into_str_args());
+                args.extend(stockpile_options.into_str_args());
                 args
             }
         }
@@ -120,9 +136,11 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
             cipher_sk,
             indexer_options,
             my_address,
+            proposer_failover_blocks,
             storage_options,
+            stockpile_options,
         } => {
-            let sign_queue = Arc::new(RwLock::new(SignQueue::new()));
+            let sign_queue = Arc::new(RwLock::new(SignQueue::new(proposer_failover_blocks)));
             let a = indexer_options.clone();
             let b = mpc_contract_id.clone();
             let c = sign_queue.clone();
@@ -135,6 +153,9 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
                 .block_on(async {
                     let (sender, receiver) = mpsc::channel(16384);
                     let key_storage = storage::init(&storage_options).await?;
+                    let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
+                    let stockpile_storage =
+                        storage::stockpile::init(&storage_options, cipher_sk.clone())?;
 
                     let my_address = my_address.unwrap_or_else(|| {
                         let my_ip = local_ip().unwrap();
@@ -154,12 +175,13 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
                         sign_queue.clone(),
                         hpke::PublicKey::try_from_bytes(&hex::decode(cipher_pk)?)?,
                         key_storage,
+                        stockpile_storage,
+                        stockpile_options,
                     );
                     tracing::debug!("protocol initialized");
                     let protocol_handle = tokio::spawn(async move { protocol.run().await });
                     tracing::debug!("protocol thread spawned");
                     let mpc_contract_id_cloned = mpc_contract_id.clone();
-                    let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
                     let web_handle = tokio::spawn(async move {
                         web::run(
                             web_port,
@@ -1,12 +1,17 @@
-use crate::protocol::contract::primitives::ParticipantInfo;
+use crate::metrics;
+use crate::protocol::contract::primitives::{ParticipantInfo, Participants};
 use crate::protocol::message::SignedMessage;
 use crate::protocol::MpcMessage;
 use cait_sith::protocol::Participant;
 use mpc_keys::hpke;
 use near_primitives::types::AccountId;
 use reqwest::{Client, IntoUrl};
-use std::collections::VecDeque;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
 use std::str::Utf8Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tokio_retry::Retry;
 
@@ -26,6 +31,21 @@ pub enum SendError {
     EncryptionError(String),
 }
 
+/// Time allowed to connect to a peer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time allowed for a whole request to a peer, so that one that hangs can not hold up the
+/// protocol.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the client used to talk to the other participants.
+pub fn client() -> Client {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("failed to build the http client")
+}
+
 async fn send_encrypted<U: IntoUrl>(
     from: Participant,
     cipher_pk: &hpke::PublicKey,
@@ -42,36 +62,32 @@ async fn send_encrypted<U: IntoUrl>(
     let mut url = url.into_url()?;
     url.set_path("msg");
     tracing::debug!(%url, "making http request");
-    let action = || async {
-        let response = client
-            .post(url.clone())
-            .header("content-type", "application/json")
-            .json(&encrypted)
-            .send()
-            .await
-            .map_err(SendError::ReqwestClientError)?;
-        let status = response.status();
-        let response_bytes = response
-            .bytes()
-            .await
-            .map_err(SendError::ReqwestBodyError)?;
-        let response_str =
-            std::str::from_utf8(&response_bytes).map_err(SendError::MalformedResponse)?;
-        if status.is_success() {
-            Ok(())
-        } else {
-            tracing::error!(
-                "failed to send a message to {} with code {}: {}",
-                url,
-                status,
-                response_str
-            );
-            Err(SendError::Unsuccessful(response_str.into()))
-        }
-    };
-
-    let retry_strategy = ExponentialBackoff::from_millis(10).map(jitter).take(3);
-    Retry::spawn(retry_strategy, action).await
+    // A single attempt, failed sends are retried by the peer's queue once it has backed off.
+    let response = client
+        .post(url.clone())
+        .header("content-type", "application/json")
+        .json(&encrypted)
+        .send()
+        .await
+        .map_err(SendError::ReqwestClientError)?;
+    let status = response.status();
+    let response_bytes = response
+        .bytes()
+        .await
+        .map_err(SendError::ReqwestBodyError)?;
+    let response_str =
+        std::str::from_utf8(&response_bytes).map_err(SendError::MalformedResponse)?;
+    if status.is_success() {
+        Ok(())
+    } else {
+        tracing::error!(
+            "failed to send a message to {} with code {}: {}",
+            url,
+            status,
+            response_str
+        );
+        Err(SendError::Unsuccessful(response_str.into()))
+    }
 }
 
 pub async fn join<U: IntoUrl>(
@@ -110,41 +126,120 @@ pub async fn join<U: IntoUrl>(
     Retry::spawn(retry_strategy, action).await
 }
 
-// TODO: add in retry logic either in struct or at call site.
-// TODO: add check for participant list to see if the messages to be sent are still valid.
-#[derive(Default)]
-pub struct MessageQueue {
-    deque: VecDeque<(ParticipantInfo, MpcMessage)>,
+/// Maximum number of messages waiting to be sent to a single peer. The oldest ones are dropped
+/// once it is exceeded.
+const MAX_QUEUED_MESSAGES_PER_PEER: usize = 4096;
+/// Time after which a message that could not be delivered is dropped. With the default
+/// generator timeout, the protocol it belongs to has been evicted as stalled by then.
+const MESSAGE_TTL: Duration = Duration::from_secs(120);
+/// Delay before retrying a peer after its first failed send, doubled on every failure after that.
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+struct QueuedMessage {
+    msg: MpcMessage,
+    queued_at: Instant,
 }
 
-impl MessageQueue {
-    pub fn len(&self) -> usize {
-        self.deque.len()
+/// The messages waiting to be sent to a single peer.
+struct PeerQueue {
+    info: ParticipantInfo,
+    messages: VecDeque<QueuedMessage>,
+    /// Failed send attempts since the last successful one.
+    failures: u32,
+    /// When the peer may be tried again after a failed send.
+    retry_at: Option<Instant>,
+    /// Whether the queued messages were taken out to be sent and are still in flight, in
+    /// which case the messages pushed in the meantime wait for them.
+    in_flight: bool,
+}
+
+impl PeerQueue {
+    fn new(info: ParticipantInfo) -> Self {
+        Self {
+            info,
+            messages: VecDeque::new(),
+            failures: 0,
+            retry_at: None,
+            in_flight: false,
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.deque.is_empty()
+    fn push(&mut self, msg: MpcMessage) {
+        if self.messages.len() >= MAX_QUEUED_MESSAGES_PER_PEER {
+            self.messages.pop_front();
+            self.dead_letter("queue_full");
+        }
+        self.messages.push_back(QueuedMessage {
+            msg,
+            queued_at: Instant::now(),
+        });
     }
 
-    pub fn push(&mut self, info: ParticipantInfo, msg: MpcMessage) {
-        self.deque.push_back((info, msg));
+    /// Drops the messages that have been waiting for longer than [`MESSAGE_TTL`].
+    fn drop_expired(&mut self, now: Instant) {
+        while let Some(queued) = self.messages.front() {
+            if now.duration_since(queued.queued_at) < MESSAGE_TTL {
+                break;
+            }
+            self.messages.pop_front();
+            self.dead_letter("expired");
+        }
     }
 
-    pub async fn send_encrypted(
+    fn dead_letter(&self, reason: &str) {
+        tracing::warn!(
+            account_id = self.info.account_id.to_string(),
+            reason,
+            "dropping a message that could not be delivered"
+        );
+        metrics::MESSAGES_DEAD_LETTERED
+            .with_label_values(&[self.info.account_id.as_str(), reason])
+            .inc();
+    }
+
+    fn backing_off(&self, now: Instant) -> bool {
+        matches!(self.retry_at, Some(retry_at) if now < retry_at)
+    }
+
+    /// Sends the queued messages in order, stopping at the first one that fails.
+    async fn send(
         &mut self,
         from: Participant,
         sign_sk: &near_crypto::SecretKey,
         client: &Client,
     ) -> Result<(), SendError> {
-        while let Some((info, msg)) = self.deque.front() {
-            let result =
-                send_encrypted(from, &info.cipher_pk, sign_sk, client, &info.url, msg).await;
+        while let Some(queued) = self.messages.front() {
+            let result = send_encrypted(
+                from,
+                &self.info.cipher_pk,
+                sign_sk,
+                client,
+                &self.info.url,
+                &queued.msg,
+            )
+            .await;
 
             match result {
                 Ok(()) => {
-                    let _ = self.deque.pop_front();
+                    let _ = self.messages.pop_front();
+                    self.failures = 0;
+                    self.retry_at = None;
                 }
                 Err(err) => {
+                    self.failures = self.failures.saturating_add(1);
+                    let backoff = BASE_BACKOFF
+                        .saturating_mul(2u32.saturating_pow(self.failures - 1))
+                        .min(MAX_BACKOFF);
+                    let backoff = backoff / 2 + jitter(backoff / 2);
+                    tracing::warn!(
+                        account_id = self.info.account_id.to_string(),
+                        failures = self.failures,
+                        ?backoff,
+                        queued = self.messages.len(),
+                        "failed to send messages, backing off"
+                    );
+                    self.retry_at = Some(Instant::now() + backoff);
                     return Err(err);
                 }
             }
@@ -154,10 +249,250 @@ impl MessageQueue {
     }
 }
 
+/// Outgoing messages, kept in a separate queue for each peer so that one that cannot be
+/// reached only holds up its own messages.
+#[derive(Default)]
+pub struct MessageQueue {
+    peers: BTreeMap<Participant, PeerQueue>,
+    /// The first error the sends that finished since the last call to `send_encrypted` ran
+    /// into.
+    failed: Option<SendError>,
+}
+
+impl MessageQueue {
+    pub fn len(&self) -> usize {
+        self.peers.values().map(|queue| queue.messages.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.values().all(|queue| queue.messages.is_empty())
+    }
+
+    pub fn push(&mut self, info: ParticipantInfo, msg: MpcMessage) {
+        self.peers
+            .entry(Participant::from(info.id))
+            .or_insert_with(|| PeerQueue::new(info))
+            .push(msg);
+    }
+
+    /// Points queued messages at the latest url and keys of their recipients.
+    pub fn update_participants(&mut self, participants: &Participants) {
+        for queue in self.peers.values_mut() {
+            if let Some(updated) = participants.find_participant_info(&queue.info.account_id) {
+                queue.info = updated.clone();
+            }
+        }
+    }
+
+    /// Takes out the queues of the peers that have messages to send, are not backing off
+    /// after a failed send and have no messages in flight, dropping the expired messages
+    /// along the way.
+    fn take_sendable(&mut self, now: Instant) -> Vec<(Participant, PeerQueue)> {
+        let mut sendable = Vec::new();
+        for (participant, queue) in self.peers.iter_mut() {
+            queue.drop_expired(now);
+            if queue.messages.is_empty() || queue.backing_off(now) || queue.in_flight {
+                continue;
+            }
+            let mut in_flight = PeerQueue::new(queue.info.clone());
+            in_flight.in_flight = true;
+            sendable.push((*participant, std::mem::replace(queue, in_flight)));
+        }
+        sendable
+    }
+
+    /// Puts back a queue taken out by [`Self::take_sendable`], ahead of the messages pushed
+    /// for the same peer in the meantime.
+    fn put_back(&mut self, participant: Participant, mut queue: PeerQueue) {
+        match self.peers.entry(participant) {
+            Entry::Vacant(entry) => {
+                entry.insert(queue);
+            }
+            Entry::Occupied(mut entry) => {
+                let newer = entry.get_mut();
+                queue.info = newer.info.clone();
+                for queued in newer.messages.drain(..) {
+                    if queue.messages.len() >= MAX_QUEUED_MESSAGES_PER_PEER {
+                        queue.messages.pop_front();
+                        queue.dead_letter("queue_full");
+                    }
+                    queue.messages.push_back(queued);
+                }
+                *newer = queue;
+            }
+        }
+    }
+
+    /// Starts sending the queued messages of all peers in the background, skipping the peers
+    /// that are backing off after a failed send or still have messages in flight. Returns the
+    /// first error the sends that finished since the last call ran into, if any.
+    ///
+    /// Each send puts back the messages it could not deliver once it is done, so neither a
+    /// peer that hangs nor one that is slow holds up the caller or the other peers, and the
+    /// queue stays free to push messages to while the requests are in flight.
+    pub async fn send_encrypted(
+        messages: &Arc<RwLock<Self>>,
+        from: Participant,
+        sign_sk: &near_crypto::SecretKey,
+        client: &Client,
+    ) -> Result<(), SendError> {
+        let mut queue = messages.write().await;
+        for (participant, mut peer) in queue.take_sendable(Instant::now()) {
+            let messages = messages.clone();
+            let sign_sk = sign_sk.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                let result = peer.send(from, &sign_sk, &client).await;
+                let mut messages = messages.write().await;
+                messages.put_back(participant, peer);
+                if let Err(err) = result {
+                    messages.failed.get_or_insert(err);
+                }
+            });
+        }
+        queue.failed.take().map_or(Ok(()), Err)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{MessageQueue, MAX_QUEUED_MESSAGES_PER_PEER, MESSAGE_TTL};
+    use crate::protocol::contract::primitives::ParticipantInfo;
     use crate::protocol::message::GeneratingMessage;
     use crate::protocol::MpcMessage;
+    use cait_sith::protocol::Participant;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::sync::RwLock;
+
+    fn participant_info(id: u32) -> ParticipantInfo {
+        ParticipantInfo {
+            id,
+            account_id: format!("p{id}.test").parse().unwrap(),
+            url: format!("http://127.0.0.1:{}", 3000 + id),
+            cipher_pk: mpc_keys::hpke::generate().1,
+            sign_pk: near_crypto::SecretKey::from_random(near_crypto::KeyType::ED25519)
+                .public_key(),
+        }
+    }
+
+    fn message(data: u8) -> MpcMessage {
+        MpcMessage::Generating(GeneratingMessage {
+            from: Participant::from(0),
+            data: vec![data],
+        })
+    }
+
+    #[test]
+    fn test_message_queue_limits() {
+        let mut queue = MessageQueue::default();
+        let (info0, info1) = (participant_info(0), participant_info(1));
+        for i in 0..=MAX_QUEUED_MESSAGES_PER_PEER {
+            queue.push(info0.clone(), message(i as u8));
+        }
+        queue.push(info1, message(0));
+        assert_eq!(queue.len(), MAX_QUEUED_MESSAGES_PER_PEER + 1);
+
+        // The oldest message to a full queue is dropped, other peers are unaffected.
+        let peer0 = &queue.peers[&Participant::from(0)];
+        assert_eq!(peer0.messages.front().unwrap().msg, message(1));
+
+        for peer in queue.peers.values_mut() {
+            peer.drop_expired(Instant::now() + MESSAGE_TTL);
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_message_queue_is_unlocked_while_sending() {
+        let mut queue = MessageQueue::default();
+        let (info0, info1) = (participant_info(0), participant_info(1));
+        queue.push(info0.clone(), message(0));
+        queue.push(info0.clone(), message(1));
+        queue.push(info1, message(0));
+        let now = Instant::now();
+        queue.peers.get_mut(&Participant::from(1)).unwrap().retry_at = Some(now + MESSAGE_TTL);
+
+        // Peers that are backing off stay in the queue.
+        let sendable = queue.take_sendable(now);
+        assert_eq!(sendable.len(), 1);
+        assert_eq!(queue.len(), 1);
+
+        // Messages pushed while the others are in flight go after them.
+        queue.push(info0, message(2));
+        for (participant, sent) in sendable {
+            queue.put_back(participant, sent);
+        }
+        let peer0 = &queue.peers[&Participant::from(0)];
+        let order: Vec<_> = peer0.messages.iter().map(|queued| &queued.msg).collect();
+        assert_eq!(order, [&message(0), &message(1), &message(2)]);
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_unresponsive_peers_do_not_hold_up_sending() {
+        // Accepts connections but never answers on them.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut info = participant_info(1);
+        info.url = format!("http://{}", listener.local_addr().unwrap());
+        let hanging = tokio::spawn(async move {
+            let mut connections = Vec::new();
+            loop {
+                connections.push(listener.accept().await.unwrap());
+            }
+        });
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let sign_sk = near_crypto::SecretKey::from_random(near_crypto::KeyType::ED25519);
+        let from = Participant::from(0);
+        let queue = Arc::new(RwLock::new(MessageQueue::default()));
+
+        queue.write().await.push(info.clone(), message(0));
+        let started = Instant::now();
+        MessageQueue::send_encrypted(&queue, from, &sign_sk, &client)
+            .await
+            .unwrap();
+        // The message is still in flight, so the next round neither waits for it nor sends
+        // the one queued after it.
+        queue.write().await.push(info, message(1));
+        MessageQueue::send_encrypted(&queue, from, &sign_sk, &client)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(queue.read().await.len(), 1);
+
+        // A single attempt times out, after which the peer backs off with both messages kept
+        // in order.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.read().await.peers[&Participant::from(1)].in_flight {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        {
+            let queue = queue.read().await;
+            let peer = &queue.peers[&Participant::from(1)];
+            assert_eq!(peer.failures, 1);
+            assert!(peer.retry_at.is_some());
+            let order: Vec<_> = peer.messages.iter().map(|queued| &queued.msg).collect();
+            assert_eq!(order, [&message(0), &message(1)]);
+        }
+        // The failure is reported once.
+        assert!(
+            MessageQueue::send_encrypted(&queue, from, &sign_sk, &client)
+                .await
+                .is_err()
+        );
+        assert!(
+            MessageQueue::send_encrypted(&queue, from, &sign_sk, &client)
+                .await
+                .is_ok()
+        );
+        hanging.abort();
+    }
 
     #[test]
     fn test_sending_encrypted_message() {
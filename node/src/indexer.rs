@@ -1,9 +1,8 @@
 use crate::kdf;
 use crate::protocol::{SignQueue, SignRequest};
+use mpc_contract::events::Event;
 use near_lake_framework::{LakeBuilder, LakeContext};
-use near_lake_primitives::actions::ActionMetaDataExt;
 use near_lake_primitives::{receipts::ExecutionStatus, AccountId};
-use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -60,12 +59,6 @@ impl Options {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SignPayload {
-    payload: [u8; 32],
-    path: String,
-}
-
 #[derive(LakeContext)]
 struct Context {
     mpc_contract_id: AccountId,
@@ -76,53 +69,93 @@ async fn handle_block(
     mut block: near_lake_primitives::block::Block,
     ctx: &Context,
 ) -> anyhow::Result<()> {
-    for action in block.actions().cloned().collect::<Vec<_>>() {
-        if action.receiver_id() == ctx.mpc_contract_id {
-            let receipt = block.receipt_by_id(&action.receipt_id()).unwrap();
-            let ExecutionStatus::SuccessReceiptId(receipt_id) = receipt.status() else {
-                continue;
-            };
-            if let Some(function_call) = action.as_function_call() {
-                if function_call.method_name() == "sign" {
-                    if let Ok(sign_payload) =
-                        serde_json::from_slice::<'_, SignPayload>(function_call.args())
-                    {
-                        if receipt.logs().is_empty() {
-                            tracing::warn!("`sign` did not produce entropy");
-                            continue;
-                        }
-                        let Ok(entropy) = serde_json::from_str::<'_, [u8; 32]>(&receipt.logs()[0])
-                        else {
-                            tracing::warn!(
-                                "`sign` did not produce entropy correctly: {:?}",
-                                receipt.logs()[0]
-                            );
-                            continue;
-                        };
-                        let epsilon =
-                            kdf::derive_epsilon(&action.predecessor_id(), &sign_payload.path);
-                        let delta = kdf::derive_delta(receipt_id, entropy);
-                        tracing::info!(
-                            receipt_id = %receipt_id,
-                            caller_id = receipt.predecessor_id().to_string(),
-                            payload = hex::encode(sign_payload.payload),
-                            entropy = hex::encode(entropy),
-                            "indexed new `sign` function call"
-                        );
-                        let mut queue = ctx.queue.write().await;
-                        queue.add(SignRequest {
-                            receipt_id,
-                            msg_hash: sign_payload.payload,
-                            epsilon,
-                            delta,
-                            entropy,
-                        });
-                        drop(queue);
-                    }
+    for receipt in block.receipts().cloned().collect::<Vec<_>>() {
+        if receipt.receiver_id() != ctx.mpc_contract_id {
+            continue;
+        }
+        // `sign` returns the promise polling the request, so requests are logged by receipts
+        // resolving to a receipt id. Responses and cancellations resolve to a value, while
+        // expiries resolve to the promise refunding the deposit.
+        let receipt_id = match receipt.status() {
+            ExecutionStatus::SuccessValue(_) => None,
+            ExecutionStatus::SuccessReceiptId(receipt_id) => Some(receipt_id),
+            _ => continue,
+        };
+        for log in receipt.logs() {
+            match Event::from_log(&log) {
+                Some(Event::SignRequested {
+                    request_id,
+                    batch_id,
+                    predecessor_id,
+                    path,
+                    payload,
+                    entropy,
+                }) => {
+                    let Some(receipt_id) = receipt_id else {
+                        continue;
+                    };
+                    let Ok(predecessor_id) = predecessor_id.as_str().parse::<AccountId>() else {
+                        tracing::warn!(%predecessor_id, "`sign` event has an invalid predecessor id");
+                        continue;
+                    };
+                    let epsilon = kdf::derive_epsilon(&predecessor_id, &path);
+                    let delta = kdf::derive_delta(receipt_id, entropy);
+                    tracing::info!(
+                        receipt_id = %receipt_id,
+                        request_id = hex::encode(request_id),
+                        batch_id = batch_id.map(hex::encode),
+                        caller_id = predecessor_id.to_string(),
+                        payload = hex::encode(payload),
+                        entropy = hex::encode(entropy),
+                        "indexed new `sign` request"
+                    );
+                    let mut queue = ctx.queue.write().await;
+                    queue.add(SignRequest {
+                        receipt_id,
+                        request_id,
+                        batch_id,
+                        msg_hash: payload,
+                        epsilon,
+                        delta,
+                        entropy,
+                        block_height: block.block_height(),
+                    });
+                    drop(queue);
+                }
+                Some(Event::SignResponded {
+                    request_id,
+                    responder,
+                    ..
+                }) => {
+                    tracing::info!(
+                        request_id = hex::encode(request_id),
+                        %responder,
+                        "indexed `sign` response, request served"
+                    );
+                    ctx.queue.write().await.remove(request_id);
+                }
+                Some(Event::SignCancelled { request_id }) => {
+                    tracing::info!(
+                        request_id = hex::encode(request_id),
+                        "indexed `sign` cancellation, request dropped"
+                    );
+                    ctx.queue.write().await.remove(request_id);
+                }
+                Some(Event::SignExpired { request_id }) => {
+                    tracing::info!(
+                        request_id = hex::encode(request_id),
+                        "indexed `sign` expiry, request dropped"
+                    );
+                    ctx.queue.write().await.remove(request_id);
                 }
+                _ => {}
             }
         }
     }
+    ctx.queue
+        .write()
+        .await
+        .set_block_height(block.block_height());
     if block.block_height() % 1000 == 0 {
         tracing::info!(block_height = block.block_height(), "indexed block")
     }
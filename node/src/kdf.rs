@@ -3,13 +3,11 @@ use crate::util::ScalarExt;
 use hkdf::Hkdf;
 use k256::elliptic_curve::CurveArithmetic;
 use k256::{Scalar, Secp256k1};
+use mpc_contract::crypto::EPSILON_DERIVATION_PREFIX;
 use near_primitives::hash::CryptoHash;
 use near_primitives::types::AccountId;
 use sha2::{Digest, Sha256};
 
-// Constant prefix that ensures epsilon derivation values are used specifically for
-// near-mpc-recovery with key derivation protocol vX.Y.Z.
-const EPSILON_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 epsilon derivation:";
 // Constant prefix that ensures delta derivation values are used specifically for
 // near-mpc-recovery with key derivation protocol vX.Y.Z.
 const DELTA_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 delta derivation:";
@@ -41,3 +39,23 @@ pub fn derive_delta(receipt_id: CryptoHash, entropy: [u8; 32]) -> Scalar {
 pub fn derive_key(public_key: PublicKey, epsilon: Scalar) -> PublicKey {
     (<Secp256k1 as CurveArithmetic>::ProjectivePoint::GENERATOR * epsilon + public_key).to_affine()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{derive_epsilon, derive_key};
+    use k256::elliptic_curve::sec1::ToEncodedPoint;
+    use k256::{ProjectivePoint, Scalar};
+
+    /// The contract's `derived_public_key` tests check the same vector, so that both sides
+    /// derive the same keys.
+    #[test]
+    fn derive_key_matches_the_contract() {
+        let root_public_key = (ProjectivePoint::GENERATOR * Scalar::from(7u64)).to_affine();
+        let epsilon = derive_epsilon(&"alice.near".parse().unwrap(), "m/44'/60'/0'/0/0");
+        let derived = derive_key(root_public_key, epsilon);
+        assert_eq!(
+            hex::encode(derived.to_encoded_point(true).as_bytes()),
+            "0277ae2f5720b80f255fab3c07f137179f9153ef03b6de775d0395b4d8fab7059d"
+        );
+    }
+}
@@ -2,6 +2,7 @@ pub mod cli;
 pub mod http_client;
 pub mod indexer;
 pub mod kdf;
+pub mod metrics;
 pub mod protocol;
 pub mod rpc_client;
 pub mod storage;
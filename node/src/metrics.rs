@@ -0,0 +1,37 @@
+use lazy_static::lazy_static;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+
+lazy_static! {
+    pub static ref GENERATOR_TIMEOUTS: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "mpc_node_generator_timeouts_total",
+            "Total count of generators evicted for making no progress, by protocol"
+        ),
+        &["protocol"]
+    )
+    .expect("can't create a metric");
+    pub static ref SIGNATURE_MESSAGES_REJECTED: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "mpc_node_signature_messages_rejected_total",
+            "Total count of signature messages dropped without being handled, by reason"
+        ),
+        &["reason"]
+    )
+    .expect("can't create a metric");
+    pub static ref SIGNATURES_PUBLISHED: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "mpc_node_signatures_published_total",
+            "Total count of signatures published by this node, by the proposer turn they were proposed at, 0 being the first proposer"
+        ),
+        &["turn"]
+    )
+    .expect("can't create a metric");
+    pub static ref MESSAGES_DEAD_LETTERED: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "mpc_node_messages_dead_lettered_total",
+            "Total count of outgoing messages dropped before they could be delivered, by peer and reason"
+        ),
+        &["peer", "reason"]
+    )
+    .expect("can't create a metric");
+}
@@ -1,4 +1,4 @@
-use super::contract::{ProtocolState, ResharingContractState};
+use super::contract::{ProtocolState, ResharingContractState, RunningContractState};
 use super::state::{
     JoiningState, NodeState, PersistentNodeData, RunningState, StartedState,
     WaitingForConsensusState,
@@ -9,8 +9,8 @@ use crate::protocol::presignature::PresignatureManager;
 use crate::protocol::signature::SignatureManager;
 use crate::protocol::state::{GeneratingState, ResharingState};
 use crate::protocol::triple::TripleManager;
-use crate::storage::{SecretNodeStorageBox, SecretStorageError};
-use crate::types::{KeygenProtocol, ReshareProtocol, SecretKeyShare};
+use crate::storage::{SecretNodeStorageBox, SecretStorageError, StockpileStorageBox};
+use crate::types::{KeygenProtocol, PublicKey, ReshareProtocol, SecretKeyShare};
 use crate::util::AffinePointExt;
 use crate::{http_client, rpc_client};
 use async_trait::async_trait;
@@ -36,6 +36,7 @@ pub trait ConsensusCtx {
     fn sign_pk(&self) -> near_crypto::PublicKey;
     fn sign_sk(&self) -> &near_crypto::SecretKey;
     fn secret_storage(&self) -> &SecretNodeStorageBox;
+    fn stockpile_storage(&self) -> StockpileStorageBox;
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -107,39 +108,17 @@ impl ConsensusProtocol for StartedState {
                                     tracing::info!(
                                         "started: contract state is running and we are already a participant"
                                     );
-                                    let participants_vec: Vec<Participant> =
-                                        contract_state.participants.keys().cloned().collect();
-                                    Ok(NodeState::Running(RunningState {
+                                    Ok(start_running(
+                                        &ctx,
                                         epoch,
-                                        participants: contract_state.participants,
-                                        threshold: contract_state.threshold,
+                                        contract_state.participants,
+                                        contract_state.threshold,
                                         private_share,
                                         public_key,
-                                        sign_queue: ctx.sign_queue(),
-                                        triple_manager: Arc::new(RwLock::new(TripleManager::new(
-                                            participants_vec.clone(),
-                                            me,
-                                            contract_state.threshold,
-                                            epoch,
-                                        ))),
-                                        presignature_manager: Arc::new(RwLock::new(
-                                            PresignatureManager::new(
-                                                participants_vec.clone(),
-                                                me,
-                                                contract_state.threshold,
-                                                epoch,
-                                            ),
-                                        )),
-                                        signature_manager: Arc::new(RwLock::new(
-                                            SignatureManager::new(
-                                                participants_vec,
-                                                me,
-                                                contract_state.public_key,
-                                                epoch,
-                                            ),
-                                        )),
-                                        messages: Default::default(),
-                                    }))
+                                        contract_state.paused,
+                                        me,
+                                        Default::default(),
+                                    ))
                                 }
                                 None => Ok(NodeState::Joining(JoiningState {
                                     participants: contract_state.participants,
@@ -255,7 +234,7 @@ impl ConsensusProtocol for GeneratingState {
                 if self.participants != contract_state.old_participants {
                     return Err(ConsensusError::MismatchedParticipants);
                 }
-                if self.threshold != contract_state.threshold {
+                if self.threshold != contract_state.old_threshold {
                     return Err(ConsensusError::MismatchedThreshold);
                 }
                 Ok(NodeState::Generating(self))
@@ -305,10 +284,20 @@ impl ConsensusProtocol for WaitingForConsensusState {
                         public_key: contract_state.public_key,
                     }))
                 }
+                Ordering::Less if contract_state.epoch + 1 == self.epoch => {
+                    tracing::warn!(
+                        "waiting(running): resharing has been cancelled, rolling back to epoch {}",
+                        contract_state.epoch
+                    );
+                    rollback_resharing(ctx, self.old_private_share, self.public_key, contract_state)
+                }
                 Ordering::Less => Err(ConsensusError::EpochRollback),
                 Ordering::Equal => {
                     tracing::info!("waiting(running): contract state has reached consensus");
-                    if contract_state.participants != self.participants {
+                    if !contract_state
+                        .participants
+                        .has_same_members(&self.participants)
+                    {
                         return Err(ConsensusError::MismatchedParticipants);
                     }
                     if contract_state.threshold != self.threshold {
@@ -317,41 +306,22 @@ impl ConsensusProtocol for WaitingForConsensusState {
                     if contract_state.public_key != self.public_key {
                         return Err(ConsensusError::MismatchedPublicKey);
                     }
-                    let participants_vec: Vec<Participant> =
-                        self.participants.keys().cloned().collect();
-
                     let me = contract_state
                         .participants
                         .find_participant(ctx.my_account_id())
                         .unwrap();
 
-                    Ok(NodeState::Running(RunningState {
-                        epoch: self.epoch,
-                        participants: self.participants,
-                        threshold: self.threshold,
-                        private_share: self.private_share,
-                        public_key: self.public_key,
-                        sign_queue: ctx.sign_queue(),
-                        triple_manager: Arc::new(RwLock::new(TripleManager::new(
-                            participants_vec.clone(),
-                            me,
-                            self.threshold,
-                            self.epoch,
-                        ))),
-                        presignature_manager: Arc::new(RwLock::new(PresignatureManager::new(
-                            participants_vec.clone(),
-                            me,
-                            self.threshold,
-                            self.epoch,
-                        ))),
-                        signature_manager: Arc::new(RwLock::new(SignatureManager::new(
-                            participants_vec,
-                            me,
-                            self.public_key,
-                            self.epoch,
-                        ))),
-                        messages: self.messages,
-                    }))
+                    Ok(start_running(
+                        &ctx,
+                        self.epoch,
+                        contract_state.participants,
+                        self.threshold,
+                        self.private_share,
+                        self.public_key,
+                        contract_state.paused,
+                        me,
+                        self.messages,
+                    ))
                 }
             },
             ProtocolState::Resharing(contract_state) => {
@@ -361,7 +331,7 @@ impl ConsensusProtocol for WaitingForConsensusState {
                         if contract_state.old_participants != self.participants {
                             return Err(ConsensusError::MismatchedParticipants);
                         }
-                        if contract_state.threshold != self.threshold {
+                        if contract_state.old_threshold != self.threshold {
                             return Err(ConsensusError::MismatchedThreshold);
                         }
                         if contract_state.public_key != self.public_key {
@@ -426,7 +396,7 @@ impl ConsensusProtocol for WaitingForConsensusState {
 #[async_trait]
 impl ConsensusProtocol for RunningState {
     async fn advance<C: ConsensusCtx + Send + Sync>(
-        self,
+        mut self,
         ctx: C,
         contract_state: ProtocolState,
     ) -> Result<NodeState, ConsensusError> {
@@ -447,7 +417,10 @@ impl ConsensusProtocol for RunningState {
                 Ordering::Less => Err(ConsensusError::EpochRollback),
                 Ordering::Equal => {
                     tracing::debug!("running(running): continuing to run as normal");
-                    if contract_state.participants != self.participants {
+                    if !contract_state
+                        .participants
+                        .has_same_members(&self.participants)
+                    {
                         return Err(ConsensusError::MismatchedParticipants);
                     }
                     if contract_state.threshold != self.threshold {
@@ -456,6 +429,21 @@ impl ConsensusProtocol for RunningState {
                     if contract_state.public_key != self.public_key {
                         return Err(ConsensusError::MismatchedPublicKey);
                     }
+                    if contract_state.participants != self.participants {
+                        tracing::info!("running(running): participant info has been updated");
+                        self.participants = contract_state.participants;
+                        self.messages
+                            .write()
+                            .await
+                            .update_participants(&self.participants);
+                    }
+                    if contract_state.paused != self.paused {
+                        tracing::info!(
+                            paused = contract_state.paused,
+                            "running(running): contract pause state has changed"
+                        );
+                        self.paused = contract_state.paused;
+                    }
                     Ok(NodeState::Running(self))
                 }
             },
@@ -499,7 +487,7 @@ impl ConsensusProtocol for RunningState {
 impl ConsensusProtocol for ResharingState {
     async fn advance<C: ConsensusCtx + Send + Sync>(
         self,
-        _ctx: C,
+        ctx: C,
         contract_state: ProtocolState,
     ) -> Result<NodeState, ConsensusError> {
         match contract_state {
@@ -517,10 +505,25 @@ impl ConsensusProtocol for ResharingState {
                             public_key: contract_state.public_key,
                         }))
                     }
+                    Ordering::Less if contract_state.epoch == self.old_epoch => {
+                        tracing::warn!(
+                            "resharing(running): resharing has been cancelled, rolling back to epoch {}",
+                            self.old_epoch
+                        );
+                        rollback_resharing(
+                            ctx,
+                            self.protocol.private_share(),
+                            self.public_key,
+                            contract_state,
+                        )
+                    }
                     Ordering::Less => Err(ConsensusError::EpochRollback),
                     Ordering::Equal => {
                         tracing::info!("resharing(running): contract state has finished resharing, trying to catch up");
-                        if contract_state.participants != self.new_participants {
+                        if !contract_state
+                            .participants
+                            .has_same_members(&self.new_participants)
+                        {
                             return Err(ConsensusError::MismatchedParticipants);
                         }
                         if contract_state.threshold != self.threshold {
@@ -561,6 +564,9 @@ impl ConsensusProtocol for ResharingState {
                         if contract_state.public_key != self.public_key {
                             return Err(ConsensusError::MismatchedPublicKey);
                         }
+                        if may_vote_cancel(&contract_state, ctx.my_account_id()) {
+                            cancel_expired_resharing(&ctx, self.old_epoch).await;
+                        }
                         Ok(NodeState::Resharing(self))
                     }
                 }
@@ -617,6 +623,21 @@ impl ConsensusProtocol for JoiningState {
                             "cipher_pk": ctx.cipher_pk().to_bytes(),
                             "sign_pk": ctx.sign_pk(),
                         });
+                        let deposit = match rpc_client::fetch_join_deposit(
+                            ctx.rpc_client(),
+                            ctx.mpc_contract_id(),
+                        )
+                        .await
+                        {
+                            Ok(deposit) => deposit,
+                            Err(err) => {
+                                tracing::warn!(
+                                    ?err,
+                                    "joining(running): failed to fetch the join deposit, retrying on the next tick"
+                                );
+                                return Ok(NodeState::Joining(self));
+                            }
+                        };
                         ctx.rpc_client()
                             .send_tx(
                                 ctx.signer(),
@@ -625,7 +646,7 @@ impl ConsensusProtocol for JoiningState {
                                     method_name: "join".to_string(),
                                     args: args.to_string().into_bytes(),
                                     gas: 300_000_000_000_000,
-                                    deposit: 0,
+                                    deposit,
                                 })],
                             )
                             .await
@@ -692,3 +713,179 @@ async fn start_resharing<C: ConsensusCtx>(
         messages: Default::default(),
     }))
 }
+
+/// Whether `me` should check if the resharing has timed out and vote to cancel it: only old
+/// participants can, and only until their vote is recorded, so the vote is sent once per epoch.
+fn may_vote_cancel(contract_state: &ResharingContractState, me: &AccountId) -> bool {
+    contract_state.old_participants.contains_account_id(me)
+        && !contract_state.cancel_votes.contains(me)
+}
+
+/// Aborts the resharing from `epoch` if the contract reports that it has timed out. Callers
+/// skip this once our cancel vote is recorded, see [`may_vote_cancel`].
+async fn cancel_expired_resharing<C: ConsensusCtx>(ctx: &C, epoch: u64) {
+    match rpc_client::fetch_resharing_expired(ctx.rpc_client(), ctx.mpc_contract_id()).await {
+        Ok(true) => {
+            tracing::warn!(
+                epoch,
+                "resharing(resharing): resharing has timed out, voting to cancel it"
+            );
+            if let Err(err) = rpc_client::vote_cancel_resharing(
+                ctx.rpc_client(),
+                ctx.signer(),
+                ctx.mpc_contract_id(),
+                epoch,
+            )
+            .await
+            {
+                tracing::warn!(
+                    ?err,
+                    "resharing(resharing): failed to vote for cancelling resharing"
+                );
+            }
+        }
+        Ok(false) => {}
+        Err(err) => {
+            tracing::warn!(
+                ?err,
+                "resharing(resharing): failed to check if resharing has timed out"
+            );
+        }
+    }
+}
+
+/// Goes back to running at the contract's epoch after a resharing has been cancelled, using
+/// the share we held before the resharing started.
+fn rollback_resharing<C: ConsensusCtx>(
+    ctx: C,
+    private_share: Option<SecretKeyShare>,
+    public_key: PublicKey,
+    contract_state: RunningContractState,
+) -> Result<NodeState, ConsensusError> {
+    if contract_state.public_key != public_key {
+        return Err(ConsensusError::MismatchedPublicKey);
+    }
+    let me = contract_state
+        .participants
+        .find_participant(ctx.my_account_id());
+    let (Some(private_share), Some(me)) = (private_share, me) else {
+        tracing::info!("we are not a part of the restored participant set, trying to rejoin");
+        return Ok(NodeState::Joining(JoiningState {
+            participants: contract_state.participants,
+            public_key,
+        }));
+    };
+    Ok(start_running(
+        &ctx,
+        contract_state.epoch,
+        contract_state.participants,
+        contract_state.threshold,
+        private_share,
+        public_key,
+        contract_state.paused,
+        me,
+        Default::default(),
+    ))
+}
+
+/// Builds the running state, with fresh stockpile managers, for `epoch` of `participants`.
+#[allow(clippy::too_many_arguments)]
+fn start_running<C: ConsensusCtx>(
+    ctx: &C,
+    epoch: u64,
+    participants: Participants,
+    threshold: usize,
+    private_share: SecretKeyShare,
+    public_key: PublicKey,
+    paused: bool,
+    me: Participant,
+    messages: Arc<RwLock<http_client::MessageQueue>>,
+) -> NodeState {
+    let participants_vec: Vec<Participant> = participants.keys().cloned().collect();
+    NodeState::Running(RunningState {
+        epoch,
+        participants,
+        threshold,
+        private_share,
+        public_key,
+        paused,
+        sign_queue: ctx.sign_queue(),
+        triple_manager: Arc::new(RwLock::new(TripleManager::new(
+            participants_vec.clone(),
+            me,
+            threshold,
+            epoch,
+            ctx.stockpile_storage(),
+        ))),
+        presignature_manager: Arc::new(RwLock::new(PresignatureManager::new(
+            participants_vec.clone(),
+            me,
+            threshold,
+            epoch,
+            ctx.stockpile_storage(),
+        ))),
+        signature_manager: Arc::new(RwLock::new(SignatureManager::new(
+            participants_vec,
+            me,
+            public_key,
+            epoch,
+        ))),
+        messages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::may_vote_cancel;
+    use crate::protocol::contract::primitives::{ParticipantInfo, Participants};
+    use crate::protocol::contract::ResharingContractState;
+    use cait_sith::protocol::Participant;
+    use k256::AffinePoint;
+    use mpc_keys::hpke;
+    use near_primitives::types::AccountId;
+    use std::collections::HashSet;
+
+    fn account(i: u32) -> AccountId {
+        format!("node{i}.test").parse().unwrap()
+    }
+
+    fn participants(ids: std::ops::Range<u32>) -> Participants {
+        Participants {
+            participants: ids
+                .map(|id| {
+                    (
+                        Participant::from(id),
+                        ParticipantInfo {
+                            id,
+                            account_id: account(id),
+                            url: "http://localhost".to_string(),
+                            cipher_pk: hpke::PublicKey::from_bytes(&[1; 32]),
+                            sign_pk: near_crypto::PublicKey::empty(near_crypto::KeyType::ED25519),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn only_old_participants_vote_to_cancel_once() {
+        let mut contract_state = ResharingContractState {
+            old_epoch: 0,
+            old_participants: participants(0..3),
+            new_participants: participants(0..4),
+            old_threshold: 2,
+            threshold: 3,
+            public_key: AffinePoint::GENERATOR,
+            finished_votes: HashSet::new(),
+            cancel_votes: HashSet::new(),
+        };
+        assert!(may_vote_cancel(&contract_state, &account(0)));
+        // The joining participant has no share of the old epoch to go back to.
+        assert!(!may_vote_cancel(&contract_state, &account(3)));
+
+        contract_state.cancel_votes.insert(account(0));
+        assert!(!may_vote_cancel(&contract_state, &account(0)));
+        assert!(may_vote_cancel(&contract_state, &account(1)));
+    }
+}
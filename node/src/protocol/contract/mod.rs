@@ -9,7 +9,7 @@ use std::{collections::HashSet, str::FromStr};
 
 use self::primitives::{Candidates, Participants, PkVotes, Votes};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InitializingContractState {
     pub candidates: Candidates,
     pub threshold: usize,
@@ -26,7 +26,7 @@ impl From<mpc_contract::InitializingContractState> for InitializingContractState
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RunningContractState {
     pub epoch: u64,
     pub participants: Participants,
@@ -35,6 +35,7 @@ pub struct RunningContractState {
     pub candidates: Candidates,
     pub join_votes: Votes,
     pub leave_votes: Votes,
+    pub paused: bool,
 }
 
 impl From<mpc_contract::RunningContractState> for RunningContractState {
@@ -47,18 +48,21 @@ impl From<mpc_contract::RunningContractState> for RunningContractState {
             candidates: value.candidates.into(),
             join_votes: value.join_votes.into(),
             leave_votes: value.leave_votes.into(),
+            paused: value.paused,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ResharingContractState {
     pub old_epoch: u64,
     pub old_participants: Participants,
     pub new_participants: Participants,
+    pub old_threshold: usize,
     pub threshold: usize,
     pub public_key: PublicKey,
     pub finished_votes: HashSet<AccountId>,
+    pub cancel_votes: HashSet<AccountId>,
 }
 
 impl From<mpc_contract::ResharingContractState> for ResharingContractState {
@@ -67,6 +71,7 @@ impl From<mpc_contract::ResharingContractState> for ResharingContractState {
             old_epoch: contract_state.old_epoch,
             old_participants: contract_state.old_participants.into(),
             new_participants: contract_state.new_participants.into(),
+            old_threshold: contract_state.old_threshold,
             threshold: contract_state.threshold,
             public_key: contract_state.public_key.into_affine_point(),
             finished_votes: contract_state
@@ -74,11 +79,16 @@ impl From<mpc_contract::ResharingContractState> for ResharingContractState {
                 .into_iter()
                 .map(|acc_id| AccountId::from_str(acc_id.as_ref()).unwrap())
                 .collect(),
+            cancel_votes: contract_state
+                .cancel_votes
+                .into_iter()
+                .map(|acc_id| AccountId::from_str(acc_id.as_ref()).unwrap())
+                .collect(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ProtocolState {
     Initializing(InitializingContractState),
     Running(RunningContractState),
@@ -1,4 +1,5 @@
 use cait_sith::protocol::Participant;
+use mpc_contract::primitives::ParticipantId;
 use mpc_keys::hpke;
 use near_primitives::{borsh::BorshDeserialize, types::AccountId};
 use serde::{Deserialize, Serialize};
@@ -7,8 +8,6 @@ use std::{
     str::FromStr,
 };
 
-type ParticipantId = u32;
-
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ParticipantInfo {
     pub id: ParticipantId,
@@ -28,17 +27,14 @@ pub struct Participants {
 impl From<mpc_contract::primitives::Participants> for Participants {
     fn from(contract_participants: mpc_contract::primitives::Participants) -> Self {
         Participants {
-            // take position of participant in contract_participants as id for participants
             participants: contract_participants
                 .participants
-                .into_iter()
-                .enumerate()
-                .map(|(participant_id, participant)| {
-                    let contract_participant_info = participant.1;
+                .into_values()
+                .map(|contract_participant_info| {
                     (
-                        Participant::from(participant_id as ParticipantId),
+                        Participant::from(contract_participant_info.id),
                         ParticipantInfo {
-                            id: participant_id as ParticipantId,
+                            id: contract_participant_info.id,
                             account_id: AccountId::from_str(
                                 contract_participant_info.account_id.as_ref(),
                             )
@@ -59,6 +55,8 @@ impl From<mpc_contract::primitives::Participants> for Participants {
     }
 }
 
+/// Numbers the candidates by their position, which is how the contract assigns the ids of
+/// the initial participants once the key has been generated.
 impl From<Candidates> for Participants {
     fn from(candidates: Candidates) -> Self {
         Participants {
@@ -134,6 +132,17 @@ impl Participants {
             .map(|participant_info| participant_info.account_id.clone())
             .collect()
     }
+
+    /// Whether both sets consist of the same accounts under the same ids. Unlike `==`, this
+    /// ignores urls and keys, which participants can update without resharing.
+    pub fn has_same_members(&self, other: &Participants) -> bool {
+        self.participants.len() == other.participants.len()
+            && self.participants.iter().zip(other.participants.iter()).all(
+                |((participant, info), (other_participant, other_info))| {
+                    participant == other_participant && info.account_id == other_info.account_id
+                },
+            )
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -234,7 +243,7 @@ impl From<mpc_contract::primitives::PkVotes> for PkVotes {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Votes {
     pub votes: BTreeMap<AccountId, HashSet<AccountId>>,
 }
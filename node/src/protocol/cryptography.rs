@@ -1,9 +1,10 @@
 use std::sync::PoisonError;
 
 use super::state::{GeneratingState, NodeState, ResharingState, RunningState};
-use crate::http_client::SendError;
-use crate::protocol::message::{GeneratingMessage, ResharingMessage};
+use crate::http_client::{MessageQueue, SendError};
+use crate::protocol::message::{GeneratingMessage, HeartbeatMessage, ResharingMessage};
 use crate::protocol::state::{PersistentNodeData, WaitingForConsensusState};
+use crate::protocol::stockpile;
 use crate::protocol::MpcMessage;
 use crate::storage::{SecretNodeStorageBox, SecretStorageError};
 use async_trait::async_trait;
@@ -23,6 +24,7 @@ pub trait CryptographicCtx {
     fn cipher_pk(&self) -> &hpke::PublicKey;
     fn sign_sk(&self) -> &near_crypto::SecretKey;
     fn secret_storage(&mut self) -> &mut SecretNodeStorageBox;
+    fn stockpile_options(&self) -> &stockpile::Options;
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -87,12 +89,13 @@ impl CryptographicProtocol for GeneratingState {
                 Action::Wait => {
                     drop(protocol);
                     tracing::debug!("generating: waiting");
-                    if let Err(err) = self
-                        .messages
-                        .write()
-                        .await
-                        .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-                        .await
+                    if let Err(err) = MessageQueue::send_encrypted(
+                        &self.messages,
+                        ctx.me().await,
+                        ctx.sign_sk(),
+                        ctx.http_client(),
+                    )
+                    .await
                     {
                         tracing::warn!(?err, participants = ?self.participants, "generating(wait): failed to send encrypted message");
                     }
@@ -140,12 +143,13 @@ impl CryptographicProtocol for GeneratingState {
                         })
                         .await?;
                     // Send any leftover messages
-                    if let Err(err) = self
-                        .messages
-                        .write()
-                        .await
-                        .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-                        .await
+                    if let Err(err) = MessageQueue::send_encrypted(
+                        &self.messages,
+                        ctx.me().await,
+                        ctx.sign_sk(),
+                        ctx.http_client(),
+                    )
+                    .await
                     {
                         tracing::warn!(?err, participants = ?self.participants, "generating(return): failed to send encrypted message");
                     }
@@ -155,6 +159,7 @@ impl CryptographicProtocol for GeneratingState {
                         threshold: self.threshold,
                         private_share: r.private_share,
                         public_key: r.public_key,
+                        old_private_share: None,
                         messages: self.messages,
                     }));
                 }
@@ -169,12 +174,13 @@ impl CryptographicProtocol for WaitingForConsensusState {
         mut self,
         ctx: C,
     ) -> Result<NodeState, CryptographicError> {
-        if let Err(err) = self
-            .messages
-            .write()
-            .await
-            .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-            .await
+        if let Err(err) = MessageQueue::send_encrypted(
+            &self.messages,
+            ctx.me().await,
+            ctx.sign_sk(),
+            ctx.http_client(),
+        )
+        .await
         {
             tracing::warn!(?err, participants = ?self.participants, "waitingForConsensus: failed to send encrypted message");
         }
@@ -207,12 +213,13 @@ impl CryptographicProtocol for ResharingState {
                 Action::Wait => {
                     drop(protocol);
                     tracing::debug!("resharing: waiting");
-                    if let Err(err) = self
-                        .messages
-                        .write()
-                        .await
-                        .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-                        .await
+                    if let Err(err) = MessageQueue::send_encrypted(
+                        &self.messages,
+                        ctx.me().await,
+                        ctx.sign_sk(),
+                        ctx.http_client(),
+                    )
+                    .await
                     {
                         tracing::warn!(?err, new = ?self.new_participants, old = ?self.old_participants, "resharing(wait): failed to send encrypted message");
                     }
@@ -256,12 +263,13 @@ impl CryptographicProtocol for ResharingState {
                     tracing::debug!("resharing: successfully completed key reshare");
 
                     // Send any leftover messages.
-                    if let Err(err) = self
-                        .messages
-                        .write()
-                        .await
-                        .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-                        .await
+                    if let Err(err) = MessageQueue::send_encrypted(
+                        &self.messages,
+                        ctx.me().await,
+                        ctx.sign_sk(),
+                        ctx.http_client(),
+                    )
+                    .await
                     {
                         tracing::warn!(?err, new = ?self.new_participants, old = ?self.old_participants, "resharing(return): failed to send encrypted message");
                     }
@@ -272,6 +280,7 @@ impl CryptographicProtocol for ResharingState {
                         threshold: self.threshold,
                         private_share,
                         public_key: self.public_key,
+                        old_private_share: self.protocol.private_share(),
                         messages: self.messages,
                     }));
                 }
@@ -286,18 +295,67 @@ impl CryptographicProtocol for RunningState {
         mut self,
         ctx: C,
     ) -> Result<NodeState, CryptographicError> {
-        let mut messages = self.messages.write().await;
         // Try sending any leftover messages donated to RunningState.
-        if let Err(err) = messages
-            .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-            .await
+        if let Err(err) = MessageQueue::send_encrypted(
+            &self.messages,
+            ctx.me().await,
+            ctx.sign_sk(),
+            ctx.http_client(),
+        )
+        .await
         {
             tracing::warn!(?err, participants = ?self.participants, "running(pre): failed to send encrypted message");
         }
+        let mut messages = self.messages.write().await;
+
+        let me = ctx.me().await;
+        // Requests this node has to propose, which each take a presignature made of two of
+        // its triples. Generation speeds up while they are waiting.
+        let (my_requests, pending) = {
+            let mut sign_queue = self.sign_queue.write().await;
+            if sign_queue.heartbeat_due() {
+                // This node is judged by its own heartbeats like every other one.
+                let seen = sign_queue.sending_heartbeat(me);
+                for (p, info) in self.participants.iter() {
+                    if *p != me {
+                        messages.push(
+                            info.clone(),
+                            MpcMessage::Heartbeat(HeartbeatMessage {
+                                epoch: self.epoch,
+                                from: me,
+                                block_height: sign_queue.block_height(),
+                                seen: seen.clone(),
+                            }),
+                        );
+                    }
+                }
+            }
+            sign_queue.organize(&self, me);
+            (sign_queue.my_requests(me), sign_queue.has_pending())
+        };
+        let backlog = my_requests.len();
+        // Pausing stops new requests from coming in, but the ones already queued still need
+        // triples and presignatures to be served.
+        let generate = !self.paused || pending;
+        if !generate {
+            tracing::debug!(
+                "running(pre): contract is paused and no requests are pending, not generating any new triples or presignatures"
+            );
+        }
+        let stockpile = ctx.stockpile_options();
 
         let mut triple_manager = self.triple_manager.write().await;
-        if triple_manager.my_len() < 2 && triple_manager.potential_len() < 10 {
-            triple_manager.generate()?;
+        triple_manager.evict_stalled(stockpile.generator_timeout());
+        if generate {
+            let to_generate = stockpile.triple_config().to_generate(
+                triple_manager.my_potential_len(),
+                triple_manager.potential_len(),
+                triple_manager.generating_len(),
+                2 * backlog,
+            );
+            for _ in 0..to_generate {
+                triple_manager.generate()?;
+            }
         }
         for (p, msg) in triple_manager.poke()? {
             let info = self.fetch_participant(&p)?;
@@ -305,21 +363,31 @@ impl CryptographicProtocol for RunningState {
         }
 
         let mut presignature_manager = self.presignature_manager.write().await;
-        if presignature_manager.my_len() < 2 && presignature_manager.potential_len() < 10 {
-            // To ensure there is no contention between different nodes we are only using triples
-            // that we proposed. This way in a non-BFT environment we are guaranteed to never try
-            // to use the same triple as any other node.
-            if let Some((triple0, triple1)) = triple_manager.take_two_mine() {
-                presignature_manager.generate(
-                    triple0,
-                    triple1,
-                    &self.public_key,
-                    &self.private_share,
-                )?;
-            } else {
-                tracing::debug!(
-                    "running(pre): we don't have enough triples to generate a presignature"
-                );
+        presignature_manager.evict_stalled(stockpile.generator_timeout());
+        if generate {
+            let to_generate = stockpile.presignature_config().to_generate(
+                presignature_manager.my_potential_len(),
+                presignature_manager.potential_len(),
+                presignature_manager.generating_len(),
+                backlog,
+            );
+            for _ in 0..to_generate {
+                // To ensure there is no contention between different nodes we are only using
+                // triples that we proposed. This way in a non-BFT environment we are guaranteed
+                // to never try to use the same triple as any other node.
+                if let Some((triple0, triple1)) = triple_manager.take_two_mine() {
+                    presignature_manager.generate(
+                        triple0,
+                        triple1,
+                        &self.public_key,
+                        &self.private_share,
+                    )?;
+                } else {
+                    tracing::debug!(
+                        "running(pre): we don't have enough triples to generate a presignature"
+                    );
+                    break;
+                }
             }
         }
         drop(triple_manager);
@@ -330,21 +398,33 @@ impl CryptographicProtocol for RunningState {
 
         let mut sign_queue = self.sign_queue.write().await;
         let mut signature_manager = self.signature_manager.write().await;
-        sign_queue.organize(&self, ctx.me().await);
-        let my_requests = sign_queue.my_requests(ctx.me().await);
-        while presignature_manager.my_len() > 0 {
-            let Some((receipt_id, _)) = my_requests.iter().next() else {
+        for request_id in signature_manager.evict_stalled(stockpile.generator_timeout()) {
+            sign_queue.unmark_proposed(request_id);
+        }
+        for request_id in my_requests {
+            // Requests in the queue were accepted before the pause, so they are still signed.
+            if presignature_manager.my_len() == 0 {
                 break;
-            };
+            }
+            // A backup proposer leaves the request alone while the protocol of a previous
+            // proposer is still running, it takes over once that one is evicted.
+            if signature_manager.is_generating(request_id) {
+                continue;
+            }
+            // The request may have been served since the queue was organized.
+            if !sign_queue.contains(me, request_id) {
+                continue;
+            }
             let Some(presignature) = presignature_manager.take_mine() else {
                 break;
             };
-            let receipt_id = *receipt_id;
-            let my_request = my_requests.remove(&receipt_id).unwrap();
+            let (my_request, turn) = sign_queue.mark_proposed(request_id).unwrap();
             signature_manager.generate(
-                receipt_id,
+                my_request.receipt_id,
+                turn,
                 presignature,
                 self.public_key,
+                request_id,
                 my_request.msg_hash,
                 my_request.epsilon,
                 my_request.delta,
@@ -356,17 +436,21 @@ impl CryptographicProtocol for RunningState {
             let info = self.participants.get(&p).unwrap();
             messages.push(info.clone(), MpcMessage::Signature(msg));
         }
+        drop(messages);
         signature_manager
             .publish(ctx.rpc_client(), ctx.signer(), ctx.mpc_contract_id())
             .await?;
         drop(signature_manager);
-        if let Err(err) = messages
-            .send_encrypted(ctx.me().await, ctx.sign_sk(), ctx.http_client())
-            .await
+        if let Err(err) = MessageQueue::send_encrypted(
+            &self.messages,
+            ctx.me().await,
+            ctx.sign_sk(),
+            ctx.http_client(),
+        )
+        .await
         {
             tracing::warn!(?err, participants = ?self.participants, "running(post): failed to send encrypted message");
         }
-        drop(messages);
 
         Ok(NodeState::Running(self))
     }
@@ -0,0 +1,101 @@
+//! Remembers the ids of generators that were evicted, so that late messages for them are
+//! dropped instead of starting them over.
+//!
+//! Messages stop arriving once their senders give up on a protocol too, so an id only has to
+//! be remembered for as long as messages are: entries are forgotten after
+//! [`SIGNATURE_BIN_TTL`], and the oldest ones are dropped early if too many pile up.
+
+use super::message::SIGNATURE_BIN_TTL;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Maximum number of evicted ids remembered at once.
+const MAX_EVICTED: usize = 65536;
+
+pub struct Evicted<Id> {
+    ids: HashSet<Id>,
+    /// The remembered ids in the order they were evicted in, which is also the order they
+    /// expire in.
+    order: VecDeque<(Id, Instant)>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl<Id: Copy + Eq + Hash> Default for Evicted<Id> {
+    fn default() -> Self {
+        Self::new(SIGNATURE_BIN_TTL, MAX_EVICTED)
+    }
+}
+
+impl<Id: Copy + Eq + Hash> Evicted<Id> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            ttl,
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn insert(&mut self, id: Id) {
+        self.prune();
+        if !self.ids.insert(id) {
+            return;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back((id, Instant::now()));
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Forgets the ids that were evicted longer than the TTL ago.
+    pub fn prune(&mut self) {
+        while let Some((id, evicted)) = self.order.front() {
+            if evicted.elapsed() < self.ttl {
+                break;
+            }
+            self.ids.remove(id);
+            self.order.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Evicted;
+    use std::time::Duration;
+
+    #[test]
+    fn ids_are_forgotten_after_the_ttl() {
+        let mut evicted = Evicted::new(Duration::from_millis(20), 16);
+        evicted.insert(1u64);
+        std::thread::sleep(Duration::from_millis(30));
+        evicted.insert(2);
+        // Inserting prunes the expired ids.
+        assert!(!evicted.contains(&1));
+        assert!(evicted.contains(&2));
+
+        std::thread::sleep(Duration::from_millis(30));
+        evicted.prune();
+        assert!(!evicted.contains(&2));
+    }
+
+    #[test]
+    fn the_oldest_ids_make_room_for_new_ones() {
+        let mut evicted = Evicted::new(Duration::from_secs(600), 3);
+        for id in 0..5u64 {
+            evicted.insert(id);
+            // Evicting the same id again does not take up more room.
+            evicted.insert(id);
+        }
+        assert!((0..2).all(|id| !evicted.contains(&id)));
+        assert!((2..5).all(|id| evicted.contains(&id)));
+    }
+}
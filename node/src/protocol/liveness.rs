@@ -0,0 +1,251 @@
+//! Tracks which participants are reachable, so that sign requests are not assigned to nodes
+//! that are offline.
+//!
+//! To keep subset selection the same across nodes, participants are judged only by the
+//! heartbeats they broadcast, each carrying the height of the last block the sender indexed,
+//! rather than by when or whether this node got through to them. This node is judged by the
+//! heartbeats it sends like every other one. Every heartbeat also passes on the sender's own
+//! view, so a heartbeat that only reached some nodes, or a view lost to a restart, reaches the
+//! rest with the next round.
+//!
+//! A request indexed at block `h` uses the view as of the last checkpoint, a multiple of
+//! [`LIVENESS_WINDOW_BLOCKS`] at or below `h - SETTLE_BLOCKS`: a participant counts as online
+//! if one of its heartbeats is from the window right before that checkpoint. That window has
+//! been over for at least [`SETTLE_BLOCKS`] by the time the request is indexed, long enough for
+//! its heartbeats to have been passed around, and heartbeats are sent much more often than it
+//! lasts, so a node that is up has one in it at every honest node and a node that is down has
+//! none anywhere.
+
+use cait_sith::protocol::Participant;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+/// Size of the block window a participant has to be heard from in to count as online.
+pub const LIVENESS_WINDOW_BLOCKS: u64 = 60;
+/// Number of blocks a window is given after it ends before requests use it, enough for a few
+/// rounds of heartbeats to pass on what was heard in it.
+pub const SETTLE_BLOCKS: u64 = 30;
+/// How often heartbeats are sent to every peer.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a node that just started waits for the views of its peers before using its own.
+const STARTUP_SETTLE_TIME: Duration = Duration::from_secs(2 * HEARTBEAT_INTERVAL.as_secs());
+/// Number of windows heartbeats are remembered for, counting back from a participant's latest
+/// one.
+const REMEMBERED_WINDOWS: u64 = 4;
+
+/// The windows each participant was heard from in, as passed on with heartbeats.
+pub type LivenessView = Vec<(Participant, Vec<u64>)>;
+
+pub struct Liveness {
+    /// The windows each participant sent heartbeats in.
+    peers: HashMap<Participant, BTreeSet<u64>>,
+    last_heartbeat: Option<Instant>,
+    started: Instant,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            peers: HashMap::new(),
+            last_heartbeat: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Liveness {
+    /// Records a heartbeat `participant` sent after indexing the block at `block_height`.
+    pub fn heard_from(&mut self, participant: Participant, block_height: u64) {
+        self.record(participant, block_height / LIVENESS_WINDOW_BLOCKS);
+    }
+
+    /// Adds what another node has heard to this node's view.
+    pub fn merge(&mut self, view: &LivenessView) {
+        for (participant, windows) in view {
+            for window in windows {
+                self.record(*participant, *window);
+            }
+        }
+    }
+
+    /// Returns this node's view, to be passed on to the other participants.
+    pub fn view(&self) -> LivenessView {
+        self.peers
+            .iter()
+            .map(|(participant, windows)| (*participant, windows.iter().copied().collect()))
+            .collect()
+    }
+
+    fn record(&mut self, participant: Participant, window: u64) {
+        let windows = self.peers.entry(participant).or_default();
+        windows.insert(window);
+        if let Some(&latest) = windows.last() {
+            let oldest = latest.saturating_sub(REMEMBERED_WINDOWS - 1);
+            *windows = windows.split_off(&oldest);
+        }
+    }
+
+    /// Whether `participant` counts as online for a request indexed at `block_height`, that
+    /// is whether it sent a heartbeat from within the window before the last checkpoint.
+    pub fn is_online(&self, participant: Participant, block_height: u64) -> bool {
+        let checkpoint = block_height.saturating_sub(SETTLE_BLOCKS) / LIVENESS_WINDOW_BLOCKS;
+        let Some(window) = checkpoint.checked_sub(1) else {
+            return false;
+        };
+        self.peers
+            .get(&participant)
+            .is_some_and(|windows| windows.contains(&window))
+    }
+
+    /// Whether this node has been up for long enough to have received the views of its peers,
+    /// without which it would take the participants it has not heard from itself for offline.
+    pub fn is_settled(&self) -> bool {
+        self.started.elapsed() >= STARTUP_SETTLE_TIME
+    }
+
+    /// Returns whether it is time to send out heartbeats, and if so records that they are
+    /// being sent.
+    pub fn heartbeat_due(&mut self) -> bool {
+        let due = match self.last_heartbeat {
+            Some(sent) => sent.elapsed() >= HEARTBEAT_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.last_heartbeat = Some(Instant::now());
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Liveness, LIVENESS_WINDOW_BLOCKS, SETTLE_BLOCKS};
+    use cait_sith::protocol::Participant;
+
+    fn online(liveness: &Liveness, peers: &[Participant], block_height: u64) -> Vec<Participant> {
+        peers
+            .iter()
+            .copied()
+            .filter(|p| liveness.is_online(*p, block_height))
+            .collect()
+    }
+
+    #[test]
+    fn peers_are_judged_by_the_last_settled_checkpoint() {
+        let peer = Participant::from(1);
+        let mut liveness = Liveness::default();
+        assert!(!liveness.is_online(peer, 150));
+
+        liveness.heard_from(peer, 70);
+        // Both requests use the checkpoint at block 120, whose window starts at block 60.
+        assert!(liveness.is_online(peer, 120 + SETTLE_BLOCKS));
+        assert!(liveness.is_online(peer, 179 + SETTLE_BLOCKS));
+        // The checkpoint at block 120 has not settled yet.
+        assert!(!liveness.is_online(peer, 120));
+        // The next checkpoint's window starts after the peer was last heard from.
+        assert!(!liveness.is_online(peer, 180 + SETTLE_BLOCKS));
+        assert!(!liveness.is_online(peer, 180 + LIVENESS_WINDOW_BLOCKS + SETTLE_BLOCKS));
+    }
+
+    #[test]
+    fn heartbeats_from_the_checkpoint_on_do_not_count() {
+        let peer = Participant::from(1);
+        let mut liveness = Liveness::default();
+        liveness.heard_from(peer, 120);
+        liveness.heard_from(peer, 150);
+        // The heartbeats are from the window of the request itself, which is still going on.
+        assert!(!liveness.is_online(peer, 130 + SETTLE_BLOCKS));
+        assert!(liveness.is_online(peer, 180 + SETTLE_BLOCKS));
+    }
+
+    #[test]
+    fn old_heartbeats_are_forgotten() {
+        let peer = Participant::from(1);
+        let mut liveness = Liveness::default();
+        liveness.heard_from(peer, 10);
+        liveness.heard_from(peer, 10 * LIVENESS_WINDOW_BLOCKS);
+        assert!(!liveness.is_online(peer, 70 + SETTLE_BLOCKS));
+        assert!(liveness.is_online(peer, 11 * LIVENESS_WINDOW_BLOCKS + SETTLE_BLOCKS));
+
+        // Views passed on by others are held to the same limit.
+        let mut other = Liveness::default();
+        other.merge(&liveness.view());
+        other.merge(&vec![(peer, vec![0])]);
+        assert!(!other.is_online(peer, 70 + SETTLE_BLOCKS));
+    }
+
+    #[test]
+    fn nodes_agree_regardless_of_when_heartbeats_arrive() {
+        let peers: Vec<Participant> = (0..4u32).map(Participant::from).collect();
+        // Peer 3 went down after its heartbeat at block 50.
+        let heartbeats = [(0, 65), (1, 70), (2, 110), (3, 50), (0, 100), (1, 118)];
+
+        // One node receives the heartbeats as they are sent.
+        let mut early = Liveness::default();
+        for (peer, block_height) in heartbeats {
+            early.heard_from(peers[peer], block_height);
+        }
+        // The other receives them later and in a different order.
+        let mut late = Liveness::default();
+        for (peer, block_height) in heartbeats.into_iter().rev() {
+            late.heard_from(peers[peer], block_height);
+        }
+
+        for block_height in [150, 180, 209] {
+            assert_eq!(online(&early, &peers, block_height), peers[..3].to_vec());
+            assert_eq!(
+                online(&early, &peers, block_height),
+                online(&late, &peers, block_height)
+            );
+        }
+    }
+
+    #[test]
+    fn heartbeats_that_reached_only_some_nodes_are_passed_on() {
+        let peers: Vec<Participant> = (0..3u32).map(Participant::from).collect();
+        let mut nodes: Vec<Liveness> = (0..3).map(|_| Liveness::default()).collect();
+        // Every node hears its own heartbeats, and peer 0's make it to node 1 only, late in
+        // the window.
+        for (i, node) in nodes.iter_mut().enumerate() {
+            node.heard_from(peers[i], 70);
+        }
+        nodes[1].heard_from(peers[0], 119);
+        nodes[1].heard_from(peers[2], 80);
+        nodes[2].heard_from(peers[1], 90);
+        assert_ne!(
+            online(&nodes[0], &peers, 150),
+            online(&nodes[2], &peers, 150)
+        );
+
+        // The next round of heartbeats, sent before the window has settled, carries the views.
+        let views: Vec<_> = nodes.iter().map(Liveness::view).collect();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            for (j, view) in views.iter().enumerate() {
+                // Node 0 still can't get through to node 2.
+                if i != j && (i, j) != (2, 0) && (i, j) != (0, 2) {
+                    node.merge(view);
+                }
+            }
+        }
+        for node in &nodes {
+            assert_eq!(online(node, &peers, 150), peers);
+        }
+    }
+
+    #[test]
+    fn restarted_nodes_recover_the_view_from_their_peers() {
+        let peers: Vec<Participant> = (0..3u32).map(Participant::from).collect();
+        let mut up = Liveness::default();
+        for (peer, block_height) in [(0, 70), (1, 75), (2, 80)] {
+            up.heard_from(peers[peer], block_height);
+        }
+
+        // Node 2 restarts, losing what it had heard, including its own heartbeats.
+        let mut restarted = Liveness::default();
+        assert!(!restarted.is_settled());
+        assert!(online(&restarted, &peers, 150).is_empty());
+        restarted.merge(&up.view());
+        assert_eq!(online(&restarted, &peers, 150), online(&up, &peers, 150));
+        assert_eq!(online(&restarted, &peers, 150), peers);
+    }
+}
@@ -1,8 +1,10 @@
 use super::cryptography::CryptographicError;
+use super::liveness::LivenessView;
 use super::presignature::{self, PresignatureId};
 use super::state::{GeneratingState, NodeState, ResharingState, RunningState};
 use super::triple::TripleId;
 use crate::http_client::SendError;
+use crate::metrics;
 use crate::storage::SecretStorageError;
 use async_trait::async_trait;
 use cait_sith::protocol::{InitializationError, MessageData, Participant, ProtocolError};
@@ -13,6 +15,7 @@ use near_primitives::hash::CryptoHash;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 #[async_trait::async_trait]
@@ -56,6 +59,7 @@ pub struct SignatureMessage {
     pub receipt_id: CryptoHash,
     pub proposer: Participant,
     pub presignature_id: PresignatureId,
+    pub request_id: [u8; 32],
     pub msg_hash: [u8; 32],
     pub epsilon: Scalar,
     pub delta: Scalar,
@@ -64,6 +68,19 @@ pub struct SignatureMessage {
     pub data: MessageData,
 }
 
+/// Sent periodically to every peer to let it know this node is online.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HeartbeatMessage {
+    pub epoch: u64,
+    pub from: Participant,
+    /// Height of the last block the sender indexed.
+    pub block_height: u64,
+    /// The windows the sender has heard each participant in, passed on so that every node
+    /// ends up with the same view.
+    #[serde(default)]
+    pub seen: LivenessView,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum MpcMessage {
     Generating(GeneratingMessage),
@@ -71,6 +88,41 @@ pub enum MpcMessage {
     Triple(TripleMessage),
     Presignature(PresignatureMessage),
     Signature(SignatureMessage),
+    Heartbeat(HeartbeatMessage),
+}
+
+impl MpcMessage {
+    pub fn from(&self) -> Participant {
+        match self {
+            MpcMessage::Generating(message) => message.from,
+            MpcMessage::Resharing(message) => message.from,
+            MpcMessage::Triple(message) => message.from,
+            MpcMessage::Presignature(message) => message.from,
+            MpcMessage::Signature(message) => message.from,
+            MpcMessage::Heartbeat(message) => message.from,
+        }
+    }
+}
+
+/// Maximum number of requests signature messages are buffered for while they can't be
+/// handled yet, usually because the request has not been indexed by this node yet.
+const MAX_BUFFERED_SIGNATURE_REQUESTS: usize = 4096;
+/// Maximum number of signature messages buffered for a single request.
+const MAX_BUFFERED_SIGNATURE_MESSAGES_PER_REQUEST: usize = 256;
+/// Maximum number of requests a single participant can have signature messages buffered for,
+/// so that one participant can not take up the whole buffer.
+const MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER: usize = 1024;
+/// How long signature messages are buffered for a request, well past the contract's default
+/// request timeout of 200 blocks.
+pub const SIGNATURE_BIN_TTL: Duration = Duration::from_secs(600);
+
+/// Signature messages buffered for a single request.
+struct SignatureBin {
+    messages: VecDeque<SignatureMessage>,
+    /// The participant whose message opened the bin.
+    opened_by: Participant,
+    /// When the bin was opened.
+    opened: Instant,
 }
 
 #[derive(Default)]
@@ -79,7 +131,7 @@ pub struct MpcMessageQueue {
     resharing_bins: HashMap<u64, VecDeque<ResharingMessage>>,
     triple_bins: HashMap<u64, HashMap<TripleId, VecDeque<TripleMessage>>>,
     presignature_bins: HashMap<u64, HashMap<PresignatureId, VecDeque<PresignatureMessage>>>,
-    signature_bins: HashMap<u64, HashMap<CryptoHash, VecDeque<SignatureMessage>>>,
+    signature_bins: HashMap<u64, HashMap<[u8; 32], SignatureBin>>,
 }
 
 impl MpcMessageQueue {
@@ -105,15 +157,97 @@ impl MpcMessageQueue {
                 .entry(message.id)
                 .or_default()
                 .push_back(message),
-            MpcMessage::Signature(message) => self
-                .signature_bins
-                .entry(message.epoch)
-                .or_default()
-                .entry(message.receipt_id)
-                .or_default()
-                .push_back(message),
+            MpcMessage::Signature(message) => {
+                let bins = self.signature_bins.entry(message.epoch).or_default();
+                if !bins.contains_key(&message.request_id) {
+                    if bins.len() >= MAX_BUFFERED_SIGNATURE_REQUESTS {
+                        tracing::warn!(
+                            request_id = hex::encode(message.request_id),
+                            from = ?message.from,
+                            "too many signature requests buffered, dropping message"
+                        );
+                        metrics::SIGNATURE_MESSAGES_REJECTED
+                            .with_label_values(&["buffer_full"])
+                            .inc();
+                        return;
+                    }
+                    let opened_by_sender = bins
+                        .values()
+                        .filter(|bin| bin.opened_by == message.from)
+                        .count();
+                    if opened_by_sender >= MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER {
+                        tracing::warn!(
+                            request_id = hex::encode(message.request_id),
+                            from = ?message.from,
+                            "too many signature requests buffered for sender, dropping message"
+                        );
+                        metrics::SIGNATURE_MESSAGES_REJECTED
+                            .with_label_values(&["sender_buffer_full"])
+                            .inc();
+                        return;
+                    }
+                }
+                let bin = bins
+                    .entry(message.request_id)
+                    .or_insert_with(|| SignatureBin {
+                        messages: VecDeque::new(),
+                        opened_by: message.from,
+                        opened: Instant::now(),
+                    });
+                if bin.messages.len() >= MAX_BUFFERED_SIGNATURE_MESSAGES_PER_REQUEST {
+                    tracing::warn!(
+                        request_id = hex::encode(message.request_id),
+                        from = ?message.from,
+                        "too many signature messages buffered for request, dropping message"
+                    );
+                    metrics::SIGNATURE_MESSAGES_REJECTED
+                        .with_label_values(&["buffer_full"])
+                        .inc();
+                    return;
+                }
+                bin.messages.push_back(message);
+            }
+            // Heartbeats only matter for liveness, which is recorded as they are received.
+            MpcMessage::Heartbeat(_) => {}
         }
     }
+
+    /// Drops the signature messages buffered for requests that are no longer pending.
+    pub fn drop_signature_bins(&mut self, request_ids: &[[u8; 32]]) {
+        for bins in self.signature_bins.values_mut() {
+            for request_id in request_ids {
+                if let Some(bin) = bins.remove(request_id) {
+                    tracing::debug!(
+                        request_id = hex::encode(request_id),
+                        msg_count = bin.messages.len(),
+                        "request is no longer pending, dropping its signature messages"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Drops the signature messages that have been buffered for longer than `ttl`, of any
+    /// epoch, along with the bins that are empty.
+    pub fn expire_signature_bins(&mut self, ttl: Duration) {
+        for bins in self.signature_bins.values_mut() {
+            bins.retain(|request_id, bin| {
+                let expired = bin.opened.elapsed() > ttl;
+                if expired && !bin.messages.is_empty() {
+                    tracing::warn!(
+                        request_id = hex::encode(request_id),
+                        msg_count = bin.messages.len(),
+                        "signature messages buffered for too long, dropping them"
+                    );
+                    metrics::SIGNATURE_MESSAGES_REJECTED
+                        .with_label_values(&["expired"])
+                        .inc_by(bin.messages.len() as u64);
+                }
+                !expired && !bin.messages.is_empty()
+            });
+        }
+        self.signature_bins.retain(|_, bins| !bins.is_empty());
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -208,16 +342,28 @@ impl MessageHandler for RunningState {
         queue: &mut MpcMessageQueue,
     ) -> Result<(), MessageHandleError> {
         let mut triple_manager = self.triple_manager.write().await;
-        for (id, queue) in queue.triple_bins.entry(self.epoch).or_default() {
-            if let Some(protocol) = triple_manager.get_or_generate(*id)? {
-                while let Some(message) = queue.pop_front() {
-                    protocol.message(message.from, message.data);
+        let triple_bins = queue.triple_bins.entry(self.epoch).or_default();
+        for (id, queue) in triple_bins.iter_mut() {
+            if queue.is_empty() {
+                continue;
+            }
+            match triple_manager.get_or_generate(*id)? {
+                Some(protocol) => {
+                    while let Some(message) = queue.pop_front() {
+                        protocol.message(message.from, message.data);
+                    }
+                }
+                None => {
+                    tracing::debug!(id, "triple already generated or evicted, dropping messages");
+                    queue.clear();
                 }
             }
         }
+        triple_bins.retain(|_, queue| !queue.is_empty());
 
         let mut presignature_manager = self.presignature_manager.write().await;
-        for (id, queue) in queue.presignature_bins.entry(self.epoch).or_default() {
+        let presignature_bins = queue.presignature_bins.entry(self.epoch).or_default();
+        for (id, queue) in presignature_bins.iter_mut() {
             let mut leftover_messages = Vec::new();
             while let Some(message) = queue.pop_front() {
                 match presignature_manager.get_or_generate(
@@ -232,6 +378,9 @@ impl MessageHandler for RunningState {
                     Err(presignature::GenerationError::AlreadyGenerated) => {
                         tracing::info!(id, "presignature already generated, nothing left to do")
                     }
+                    Err(presignature::GenerationError::Evicted) => {
+                        tracing::debug!(id, "presignature was evicted, dropping message")
+                    }
                     Err(presignature::GenerationError::TripleIsMissing(_)) => {
                         // Store the message until we are ready to process it
                         leftover_messages.push(message)
@@ -249,29 +398,72 @@ impl MessageHandler for RunningState {
                 queue.extend(leftover_messages);
             }
         }
+        presignature_bins.retain(|_, queue| !queue.is_empty());
 
+        let mut sign_queue = self.sign_queue.write().await;
+        queue.drop_signature_bins(&sign_queue.take_removed());
         let mut signature_manager = self.signature_manager.write().await;
-        for (receipt_id, queue) in queue.signature_bins.entry(self.epoch).or_default() {
+        let signature_bins = queue.signature_bins.entry(self.epoch).or_default();
+        for (request_id, bin) in signature_bins.iter_mut() {
+            let queue = &mut bin.messages;
             let mut leftover_messages = Vec::new();
             while let Some(message) = queue.pop_front() {
                 tracing::info!(
                     presignature_id = message.presignature_id,
                     "new signature message"
                 );
-                // if !self
-                //     .sign_queue
-                //     .read()
-                //     .await
-                //     .contains(message.proposer, receipt_id.clone())
-                // {
-                //     leftover_messages.push(message);
-                //     continue;
-                // };
-                // TODO: Validate that the message matches our sign_queue
+                // Only sign what this node has indexed on its own. Once it has joined the
+                // protocol the request is checked against the generator instead, as the
+                // proposer no longer has it queued. Each proposer runs its own protocol.
+                let expected = match signature_manager.generator(*request_id, message.proposer) {
+                    Some(generator) => (
+                        generator.proposer,
+                        generator.presignature_id,
+                        generator.msg_hash,
+                        generator.epsilon,
+                        generator.delta,
+                    ),
+                    None => match sign_queue.get(message.proposer, *request_id) {
+                        Some(request) => (
+                            message.proposer,
+                            message.presignature_id,
+                            request.msg_hash,
+                            request.epsilon,
+                            request.delta,
+                        ),
+                        None => {
+                            // Store the message until the request is indexed
+                            leftover_messages.push(message);
+                            continue;
+                        }
+                    },
+                };
+                if expected
+                    != (
+                        message.proposer,
+                        message.presignature_id,
+                        message.msg_hash,
+                        message.epsilon,
+                        message.delta,
+                    )
+                {
+                    tracing::warn!(
+                        request_id = hex::encode(request_id),
+                        from = ?message.from,
+                        proposer = ?message.proposer,
+                        presignature_id = message.presignature_id,
+                        "signature message does not match the indexed request, rejecting"
+                    );
+                    metrics::SIGNATURE_MESSAGES_REJECTED
+                        .with_label_values(&["mismatch"])
+                        .inc();
+                    continue;
+                }
                 match signature_manager.get_or_generate(
-                    *receipt_id,
+                    message.receipt_id,
                     message.proposer,
                     message.presignature_id,
+                    *request_id,
                     message.msg_hash,
                     message.epsilon,
                     message.delta,
@@ -292,6 +484,7 @@ impl MessageHandler for RunningState {
                 queue.extend(leftover_messages);
             }
         }
+        queue.expire_signature_bins(SIGNATURE_BIN_TTL);
         Ok(())
     }
 }
@@ -381,3 +574,123 @@ where
         Ok(serde_json::from_slice(&msg)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MpcMessage, MpcMessageQueue, SignatureMessage, MAX_BUFFERED_SIGNATURE_MESSAGES_PER_REQUEST,
+        MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER,
+    };
+    use crate::protocol::signature::SignQueue;
+    use cait_sith::protocol::Participant;
+    use k256::Scalar;
+    use near_primitives::hash::CryptoHash;
+    use std::time::Duration;
+
+    fn signature_message(from: u32, request_id: [u8; 32]) -> MpcMessage {
+        MpcMessage::Signature(SignatureMessage {
+            receipt_id: CryptoHash::default(),
+            proposer: Participant::from(0u32),
+            presignature_id: 1,
+            request_id,
+            msg_hash: [0; 32],
+            epsilon: Scalar::ONE,
+            delta: Scalar::ONE,
+            epoch: 0,
+            from: Participant::from(from),
+            data: vec![1, 2, 3],
+        })
+    }
+
+    fn request_id(i: usize) -> [u8; 32] {
+        let mut request_id = [0; 32];
+        request_id[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        request_id
+    }
+
+    fn buffered(queue: &MpcMessageQueue, request_id: [u8; 32]) -> usize {
+        queue
+            .signature_bins
+            .get(&0)
+            .and_then(|bins| bins.get(&request_id))
+            .map_or(0, |bin| bin.messages.len())
+    }
+
+    #[test]
+    fn signature_messages_are_buffered_per_request() {
+        let mut queue = MpcMessageQueue::default();
+        queue.push(signature_message(1, request_id(1)));
+        queue.push(signature_message(2, request_id(1)));
+        queue.push(signature_message(1, request_id(2)));
+        assert_eq!(buffered(&queue, request_id(1)), 2);
+        assert_eq!(buffered(&queue, request_id(2)), 1);
+        assert_eq!(
+            queue.signature_bins[&0][&request_id(1)].opened_by,
+            1u32.into()
+        );
+    }
+
+    #[test]
+    fn signature_messages_over_the_limits_are_rejected() {
+        let mut queue = MpcMessageQueue::default();
+        for _ in 0..=MAX_BUFFERED_SIGNATURE_MESSAGES_PER_REQUEST {
+            queue.push(signature_message(1, request_id(0)));
+        }
+        assert_eq!(
+            buffered(&queue, request_id(0)),
+            MAX_BUFFERED_SIGNATURE_MESSAGES_PER_REQUEST
+        );
+
+        // A single sender can only open so many bins, the others can still open theirs.
+        for i in 1..=MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER {
+            queue.push(signature_message(2, request_id(i)));
+        }
+        assert_eq!(
+            buffered(
+                &queue,
+                request_id(MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER - 1)
+            ),
+            1
+        );
+        assert_eq!(
+            buffered(
+                &queue,
+                request_id(MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER)
+            ),
+            0
+        );
+        // Bins opened by others still take messages from the sender.
+        queue.push(signature_message(2, request_id(0)));
+        queue.push(signature_message(
+            3,
+            request_id(MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER),
+        ));
+        assert_eq!(
+            buffered(
+                &queue,
+                request_id(MAX_BUFFERED_SIGNATURE_REQUESTS_PER_SENDER)
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn signature_bins_are_dropped_once_removed_or_expired() {
+        let mut queue = MpcMessageQueue::default();
+        queue.push(signature_message(1, request_id(1)));
+        queue.push(signature_message(1, request_id(2)));
+
+        let mut sign_queue = SignQueue::new(1);
+        sign_queue.remove(request_id(1));
+        queue.drop_signature_bins(&sign_queue.take_removed());
+        assert!(sign_queue.take_removed().is_empty());
+        assert_eq!(buffered(&queue, request_id(1)), 0);
+        assert_eq!(buffered(&queue, request_id(2)), 1);
+
+        queue.expire_signature_bins(Duration::from_secs(600));
+        assert_eq!(buffered(&queue, request_id(2)), 1);
+        std::thread::sleep(Duration::from_millis(1));
+        queue.expire_signature_bins(Duration::ZERO);
+        assert!(queue.signature_bins.is_empty());
+    }
+}
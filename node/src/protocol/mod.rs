@@ -1,5 +1,7 @@
 pub mod contract;
 mod cryptography;
+mod evicted;
+mod liveness;
 mod presignature;
 mod signature;
 mod triple;
@@ -7,6 +9,7 @@ mod triple;
 pub mod consensus;
 pub mod message;
 pub mod state;
+pub mod stockpile;
 
 pub use consensus::ConsensusError;
 pub use contract::primitives::ParticipantInfo;
@@ -15,16 +18,18 @@ pub use cryptography::CryptographicError;
 pub use message::MpcMessage;
 pub use signature::SignQueue;
 pub use signature::SignRequest;
+pub use signature::DEFAULT_PROPOSER_FAILOVER_BLOCKS;
 pub use state::NodeState;
 
 use self::consensus::ConsensusCtx;
 use self::cryptography::CryptographicCtx;
 use self::message::MessageCtx;
+use crate::http_client;
 use crate::protocol::consensus::ConsensusProtocol;
 use crate::protocol::cryptography::CryptographicProtocol;
 use crate::protocol::message::{MessageHandler, MpcMessageQueue};
 use crate::rpc_client::{self};
-use crate::storage::SecretNodeStorageBox;
+use crate::storage::{SecretNodeStorageBox, StockpileStorageBox};
 use cait_sith::protocol::Participant;
 use near_crypto::InMemorySigner;
 use near_primitives::types::AccountId;
@@ -47,6 +52,8 @@ struct Ctx {
     cipher_pk: hpke::PublicKey,
     sign_sk: near_crypto::SecretKey,
     secret_storage: SecretNodeStorageBox,
+    stockpile_storage: StockpileStorageBox,
+    stockpile_options: stockpile::Options,
 }
 
 impl ConsensusCtx for &MpcSignProtocol {
@@ -93,6 +100,10 @@ impl ConsensusCtx for &MpcSignProtocol {
     fn secret_storage(&self) -> &SecretNodeStorageBox {
         &self.ctx.secret_storage
     }
+
+    fn stockpile_storage(&self) -> StockpileStorageBox {
+        self.ctx.stockpile_storage.clone()
+    }
 }
 
 #[async_trait::async_trait]
@@ -128,6 +139,10 @@ impl CryptographicCtx for &mut MpcSignProtocol {
     fn secret_storage(&mut self) -> &mut SecretNodeStorageBox {
         &mut self.ctx.secret_storage
     }
+
+    fn stockpile_options(&self) -> &stockpile::Options {
+        &self.ctx.stockpile_options
+    }
 }
 
 #[async_trait::async_trait]
@@ -155,6 +170,8 @@ impl MpcSignProtocol {
         sign_queue: Arc<RwLock<SignQueue>>,
         cipher_pk: hpke::PublicKey,
         secret_storage: SecretNodeStorageBox,
+        stockpile_storage: StockpileStorageBox,
+        stockpile_options: stockpile::Options,
     ) -> (Self, Arc<RwLock<NodeState>>) {
         let state = Arc::new(RwLock::new(NodeState::Starting));
         let ctx = Ctx {
@@ -162,12 +179,14 @@ impl MpcSignProtocol {
             account_id,
             mpc_contract_id,
             rpc_client,
-            http_client: reqwest::Client::new(),
+            http_client: http_client::client(),
             sign_queue,
             cipher_pk,
             sign_sk: signer.secret_key.clone(),
             signer,
             secret_storage,
+            stockpile_storage,
+            stockpile_options,
         };
         let protocol = MpcSignProtocol {
             ctx,
@@ -180,28 +199,57 @@ impl MpcSignProtocol {
     pub async fn run(mut self) -> anyhow::Result<()> {
         let _span = tracing::info_span!("running", my_account_id = self.ctx.account_id.to_string());
         let mut queue = MpcMessageQueue::default();
+        // The last fetched contract state along with its hash, so that the full state only
+        // has to be fetched again once it changes.
+        let mut cached_contract_state: Option<([u8; 32], ProtocolState)> = None;
         loop {
             tracing::debug!("trying to advance mpc recovery protocol");
-            let contract_state = match rpc_client::fetch_mpc_contract_state(
+            let state_hash = match rpc_client::fetch_mpc_contract_state_hash(
                 &self.ctx.rpc_client,
                 &self.ctx.mpc_contract_id,
             )
             .await
             {
-                Ok(contract_state) => contract_state,
+                Ok(state_hash) => Some(state_hash),
                 Err(e) => {
-                    tracing::error!("could not fetch contract's state: {e}");
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                    continue;
+                    tracing::warn!("could not fetch contract's state hash: {e}");
+                    None
                 }
             };
+            let contract_state = match &cached_contract_state {
+                Some((cached_hash, contract_state)) if Some(*cached_hash) == state_hash => {
+                    contract_state.clone()
+                }
+                _ => match rpc_client::fetch_mpc_contract_state(
+                    &self.ctx.rpc_client,
+                    &self.ctx.mpc_contract_id,
+                )
+                .await
+                {
+                    Ok(contract_state) => {
+                        cached_contract_state =
+                            state_hash.map(|state_hash| (state_hash, contract_state.clone()));
+                        contract_state
+                    }
+                    Err(e) => {
+                        tracing::error!("could not fetch contract's state: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+            };
             tracing::debug!(?contract_state);
+            let mut heartbeats = Vec::new();
             loop {
                 let msg_result = self.receiver.try_recv();
                 match msg_result {
                     Ok(msg) => {
                         tracing::debug!("received a new message");
-                        queue.push(msg);
+                        match msg {
+                            // Heartbeats only matter for liveness, which is recorded below.
+                            MpcMessage::Heartbeat(heartbeat) => heartbeats.push(heartbeat),
+                            msg => queue.push(msg),
+                        }
                     }
                     Err(TryRecvError::Empty) => {
                         tracing::debug!("no new messages received");
@@ -213,6 +261,12 @@ impl MpcSignProtocol {
                     }
                 }
             }
+            if !heartbeats.is_empty() {
+                let mut sign_queue = self.ctx.sign_queue.write().await;
+                for heartbeat in &heartbeats {
+                    sign_queue.heard_from(heartbeat);
+                }
+            }
 
             let state = {
                 let guard = self.state.read().await;
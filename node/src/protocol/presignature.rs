@@ -1,12 +1,17 @@
+use super::evicted::Evicted;
 use super::message::PresignatureMessage;
 use super::triple::{Triple, TripleId, TripleManager};
+use crate::metrics;
+use crate::storage::{StockpileKind, StockpileStorageBox};
 use crate::types::{PresignatureProtocol, PublicKey, SecretKeyShare};
 use crate::util::AffinePointExt;
 use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
 use cait_sith::{KeygenOutput, PresignArguments, PresignOutput};
 use k256::Secp256k1;
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 /// Unique number used to identify a specific ongoing presignature generation protocol.
 /// Without `PresignatureId` it would be unclear where to route incoming cait-sith presignature
@@ -14,6 +19,7 @@ use std::collections::{HashMap, VecDeque};
 pub type PresignatureId = u64;
 
 /// A completed presignature.
+#[derive(Serialize, Deserialize)]
 pub struct Presignature {
     pub id: PresignatureId,
     pub output: PresignOutput<Secp256k1>,
@@ -25,12 +31,18 @@ pub struct PresignatureGenerator {
     pub triple0: TripleId,
     pub triple1: TripleId,
     pub mine: bool,
+    /// When the generation started.
+    pub started: Instant,
+    /// The last time the protocol made progress.
+    pub last_progress: Instant,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum GenerationError {
     #[error("presignature already generated")]
     AlreadyGenerated,
+    #[error("presignature generation was evicted")]
+    Evicted,
     #[error("triple {0} is missing")]
     TripleIsMissing(TripleId),
     #[error("cait-sith initialization error: {0}")]
@@ -44,6 +56,9 @@ pub struct PresignatureManager {
     presignatures: HashMap<PresignatureId, Presignature>,
     /// Ongoing triple generation protocols.
     generators: HashMap<PresignatureId, PresignatureGenerator>,
+    /// Presignatures of this epoch whose generation was evicted, so that late messages do not
+    /// start them again.
+    evicted: Evicted<PresignatureId>,
     /// List of presignature ids generation of which was initiated by the current node.
     mine: VecDeque<PresignatureId>,
 
@@ -51,23 +66,73 @@ pub struct PresignatureManager {
     me: Participant,
     threshold: usize,
     epoch: u64,
+    /// Keeps unspent presignatures around across restarts.
+    storage: StockpileStorageBox,
 }
 
 impl PresignatureManager {
+    /// Creates a manager holding the unspent presignatures of `epoch` kept in `storage`.
     pub fn new(
         participants: Vec<Participant>,
         me: Participant,
         threshold: usize,
         epoch: u64,
+        storage: StockpileStorageBox,
     ) -> Self {
+        let mut presignatures = HashMap::new();
+        let mut mine = VecDeque::new();
+        match storage.load(StockpileKind::Presignature, epoch) {
+            Ok(stored) => {
+                for data in stored {
+                    match serde_json::from_slice::<(Presignature, bool)>(&data) {
+                        Ok((presignature, presignature_is_mine)) => {
+                            if presignature_is_mine {
+                                mine.push_back(presignature.id);
+                            }
+                            presignatures.insert(presignature.id, presignature);
+                        }
+                        Err(err) => {
+                            tracing::error!(?err, "failed to parse a stored presignature")
+                        }
+                    }
+                }
+                tracing::info!(
+                    epoch,
+                    loaded = presignatures.len(),
+                    "loaded stored presignatures"
+                );
+            }
+            Err(err) => tracing::error!(?err, epoch, "failed to load stored presignatures"),
+        }
         Self {
-            presignatures: HashMap::new(),
+            presignatures,
             generators: HashMap::new(),
-            mine: VecDeque::new(),
+            evicted: Evicted::default(),
+            mine,
             participants,
             me,
             threshold,
             epoch,
+            storage,
+        }
+    }
+
+    /// Removes a presignature from storage as it is being spent. If this fails the
+    /// presignature will be loaded again after a restart, so the caller must not spend it.
+    fn forget(&self, id: PresignatureId) -> bool {
+        match self
+            .storage
+            .remove(StockpileKind::Presignature, self.epoch, id)
+        {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    id,
+                    "failed to remove a spent presignature from storage"
+                );
+                false
+            }
         }
     }
 
@@ -87,6 +152,17 @@ impl PresignatureManager {
         self.presignatures.len() + self.generators.len()
     }
 
+    /// Returns the number of unspent presignatures assigned to this node once all ongoing
+    /// generation protocols complete.
+    pub fn my_potential_len(&self) -> usize {
+        self.my_len() + self.generators.values().filter(|g| g.mine).count()
+    }
+
+    /// Returns the number of ongoing generation protocols.
+    pub fn generating_len(&self) -> usize {
+        self.generators.len()
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn generate_internal(
         participants: &[Participant],
@@ -111,11 +187,14 @@ impl PresignatureManager {
                 threshold,
             },
         )?);
+        let now = Instant::now();
         Ok(PresignatureGenerator {
             protocol,
             triple0: triple0.id,
             triple1: triple1.id,
             mine,
+            started: now,
+            last_progress: now,
         })
     }
 
@@ -143,11 +222,41 @@ impl PresignatureManager {
         Ok(())
     }
 
+    /// Drops the generators that have not made progress within `timeout`, most likely because
+    /// one of the participants went offline. Their triples are discarded rather than put back,
+    /// as the other participants may have already used their shares of them.
+    pub fn evict_stalled(&mut self, timeout: Duration) {
+        self.evicted.prune();
+        self.generators.retain(|id, generator| {
+            let stalled = generator.last_progress.elapsed() > timeout;
+            if stalled {
+                tracing::warn!(
+                    id,
+                    triple0 = generator.triple0,
+                    triple1 = generator.triple1,
+                    mine = generator.mine,
+                    age = ?generator.started.elapsed(),
+                    idle = ?generator.last_progress.elapsed(),
+                    "evicting stalled presignature generator, discarding its triples"
+                );
+                metrics::GENERATOR_TIMEOUTS
+                    .with_label_values(&["presignature"])
+                    .inc();
+                self.evicted.insert(*id);
+            }
+            !stalled
+        });
+    }
+
     /// Ensures that the presignature with the given id is either:
     /// 1) Already generated in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
     /// 3) Has never been seen by the manager in which case start a new protocol and returns `Some(protocol)`, or
-    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node
+    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node, or
+    /// 5) Was evicted, or depends on an evicted triple, in which case the message is of no use
+    /// anymore.
+    ///
+    /// This is called as messages for the presignature arrive, so it counts as progress.
     // TODO: What if the presignature completed generation and is already spent?
     pub fn get_or_generate(
         &mut self,
@@ -160,10 +269,20 @@ impl PresignatureManager {
     ) -> Result<&mut PresignatureProtocol, GenerationError> {
         if self.presignatures.contains_key(&id) {
             Err(GenerationError::AlreadyGenerated)
+        } else if self.evicted.contains(&id) {
+            Err(GenerationError::Evicted)
         } else {
             match self.generators.entry(id) {
                 Entry::Vacant(entry) => {
                     tracing::info!(id, "joining protocol to generate a new presignature");
+                    // The triples will never complete, so neither will the presignature.
+                    if triple_manager.evicted.contains(&triple0)
+                        || triple_manager.evicted.contains(&triple1)
+                    {
+                        tracing::warn!(triple0, triple1, "one of the triples was evicted");
+                        self.evicted.insert(id);
+                        return Err(GenerationError::Evicted);
+                    }
                     let (triple0, triple1) = match triple_manager.take_two(triple0, triple1) {
                         Ok(result) => result,
                         Err(missing_triple_id) => {
@@ -188,7 +307,11 @@ impl PresignatureManager {
                     let generator = entry.insert(generator);
                     Ok(&mut generator.protocol)
                 }
-                Entry::Occupied(entry) => Ok(&mut entry.into_mut().protocol),
+                Entry::Occupied(entry) => {
+                    let generator = entry.into_mut();
+                    generator.last_progress = Instant::now();
+                    Ok(&mut generator.protocol)
+                }
             }
         }
     }
@@ -196,11 +319,13 @@ impl PresignatureManager {
     pub fn take_mine(&mut self) -> Option<Presignature> {
         tracing::info!(mine = ?self.mine, "my presignatures");
         let my_presignature_id = self.mine.pop_front()?;
-        Some(self.presignatures.remove(&my_presignature_id).unwrap())
+        let presignature = self.presignatures.remove(&my_presignature_id).unwrap();
+        self.forget(my_presignature_id).then_some(presignature)
     }
 
     pub fn take(&mut self, id: PresignatureId) -> Option<Presignature> {
-        self.presignatures.remove(&id)
+        let presignature = self.presignatures.remove(&id)?;
+        self.forget(id).then_some(presignature)
     }
 
     /// Pokes all of the ongoing generation protocols and returns a vector of
@@ -220,6 +345,9 @@ impl PresignatureManager {
                         break false;
                     }
                 };
+                if !matches!(action, Action::Wait) {
+                    generator.last_progress = Instant::now();
+                }
                 match action {
                     Action::Wait => {
                         tracing::debug!("waiting");
@@ -256,10 +384,28 @@ impl PresignatureManager {
                         tracing::info!(
                             id,
                             big_r = ?output.big_r.to_base58(),
+                            elapsed = ?generator.started.elapsed(),
                             "completed presignature generation"
                         );
-                        self.presignatures
-                            .insert(*id, Presignature { id: *id, output });
+                        let presignature = Presignature { id: *id, output };
+                        // A presignature that could not be stored is still usable until a
+                        // restart.
+                        match serde_json::to_vec(&(&presignature, generator.mine)) {
+                            Ok(data) => {
+                                if let Err(err) = self.storage.store(
+                                    StockpileKind::Presignature,
+                                    self.epoch,
+                                    *id,
+                                    &data,
+                                ) {
+                                    tracing::warn!(?err, id, "failed to store a presignature");
+                                }
+                            }
+                            Err(err) => {
+                                tracing::warn!(?err, id, "failed to serialize a presignature")
+                            }
+                        }
+                        self.presignatures.insert(*id, presignature);
                         if generator.mine {
                             tracing::info!(id, "assigning presignature to myself");
                             self.mine.push_back(*id);
@@ -273,3 +419,91 @@ impl PresignatureManager {
         result.map(|_| messages)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GenerationError, Presignature, PresignatureManager};
+    use crate::protocol::triple::TripleManager;
+    use crate::storage::{stockpile, StockpileKind, StockpileStorage, StockpileStorageBox};
+    use cait_sith::protocol::Participant;
+    use cait_sith::PresignOutput;
+    use k256::{AffinePoint, Scalar};
+
+    fn store(storage: &StockpileStorageBox, epoch: u64, id: u64, mine: bool) {
+        let presignature = Presignature {
+            id,
+            output: PresignOutput {
+                big_r: AffinePoint::GENERATOR,
+                k: Scalar::ONE,
+                sigma: Scalar::ONE,
+            },
+        };
+        let data = serde_json::to_vec(&(presignature, mine)).unwrap();
+        storage
+            .store(StockpileKind::Presignature, epoch, id, &data)
+            .unwrap();
+    }
+
+    fn manager(storage: &StockpileStorageBox, epoch: u64) -> PresignatureManager {
+        let participants: Vec<Participant> = (0..3u32).map(Participant::from).collect();
+        PresignatureManager::new(
+            participants,
+            Participant::from(0u32),
+            2,
+            epoch,
+            storage.clone(),
+        )
+    }
+
+    #[test]
+    fn stored_presignatures_are_reloaded_unless_spent() {
+        let storage = stockpile::memory();
+        store(&storage, 0, 1, false);
+        store(&storage, 1, 2, true);
+        store(&storage, 1, 3, false);
+
+        let mut manager1 = manager(&storage, 1);
+        assert_eq!(manager1.len(), 2);
+        assert_eq!(manager1.my_len(), 1);
+        assert_eq!(manager1.take_mine().unwrap().id, 2);
+        assert!(manager1.take_mine().is_none());
+
+        // The spent presignature is gone after a restart, the other one is kept.
+        let mut manager1 = manager(&storage, 1);
+        assert_eq!(manager1.len(), 1);
+        assert_eq!(manager1.my_len(), 0);
+        assert!(manager1.take(3).is_some());
+        assert_eq!(manager(&storage, 1).len(), 0);
+
+        // Presignatures of a past epoch were pruned when epoch 1 was loaded.
+        assert_eq!(manager(&storage, 0).len(), 0);
+    }
+
+    #[test]
+    fn presignatures_of_evicted_triples_are_not_generated() {
+        let storage = stockpile::memory();
+        let mut manager = manager(&storage, 0);
+        let mut triple_manager = TripleManager::new(
+            manager.participants.clone(),
+            manager.me,
+            manager.threshold,
+            0,
+            storage.clone(),
+        );
+        triple_manager.evicted.insert(7);
+
+        for _ in 0..2 {
+            let result = manager.get_or_generate(
+                1,
+                7,
+                8,
+                &mut triple_manager,
+                &AffinePoint::GENERATOR,
+                &Scalar::ONE,
+            );
+            assert!(matches!(result, Err(GenerationError::Evicted)));
+        }
+        assert!(manager.evicted.contains(&1));
+        assert_eq!(manager.generating_len(), 0);
+    }
+}
@@ -1,12 +1,17 @@
-use super::message::SignatureMessage;
+use super::liveness::{Liveness, LivenessView};
+use super::message::{HeartbeatMessage, SignatureMessage};
 use super::presignature::{Presignature, PresignatureId, PresignatureManager};
 use super::state::RunningState;
 use crate::kdf;
+use crate::metrics;
 use crate::types::{PublicKey, SignatureProtocol};
 use crate::util::{AffinePointExt, ScalarExt};
 use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
 use cait_sith::{FullSignature, PresignOutput};
+use k256::elliptic_curve::point::AffineCoordinates;
+use k256::elliptic_curve::scalar::IsHigh;
 use k256::{Scalar, Secp256k1};
+use mpc_contract::primitives::SignatureResponse;
 use near_crypto::Signer;
 use near_fetch::signer::ExposeAccountId;
 use near_primitives::hash::CryptoHash;
@@ -16,92 +21,288 @@ use rand::rngs::StdRng;
 use rand::seq::{IteratorRandom, SliceRandom};
 use rand::SeedableRng;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Number of blocks after which a request's next proposer takes over, unless the request has
+/// been served by then.
+pub const DEFAULT_PROPOSER_FAILOVER_BLOCKS: u64 = 30;
+/// Maximum number of removed requests remembered until their buffered messages are dropped.
+const MAX_REMOVED_REQUESTS: usize = 4096;
 
 pub struct SignRequest {
     pub receipt_id: CryptoHash,
+    pub request_id: [u8; 32],
+    /// The id of the batch this request was made in, if it was made through `sign_batch`.
+    pub batch_id: Option<[u8; 32]>,
     pub msg_hash: [u8; 32],
     pub epsilon: Scalar,
     pub delta: Scalar,
     pub entropy: [u8; 32],
+    /// Height of the block the request was indexed in.
+    pub block_height: u64,
+}
+
+/// A sign request this node is one of the signers of.
+struct QueuedRequest {
+    request: SignRequest,
+    /// The signers in the order in which they take over proposing the request.
+    proposers: Vec<Participant>,
+    /// Whether this node has proposed the request already.
+    proposed: bool,
 }
 
-#[derive(Default)]
 pub struct SignQueue {
     unorganized_requests: Vec<SignRequest>,
-    requests: HashMap<Participant, HashMap<CryptoHash, SignRequest>>,
+    requests: HashMap<[u8; 32], QueuedRequest>,
+    /// Requests removed since the last call to `take_removed`.
+    removed: VecDeque<[u8; 32]>,
+    /// Height of the last indexed block.
+    block_height: u64,
+    /// Number of blocks after which the next proposer of a request takes over.
+    failover_blocks: u64,
+    liveness: Liveness,
 }
 
 impl SignQueue {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(failover_blocks: u64) -> Self {
+        Self {
+            unorganized_requests: Vec::new(),
+            requests: HashMap::new(),
+            removed: VecDeque::new(),
+            block_height: 0,
+            failover_blocks: failover_blocks.max(1),
+            liveness: Liveness::default(),
+        }
     }
 
     pub fn add(&mut self, request: SignRequest) {
         tracing::info!(
             receipt_id = %request.receipt_id,
+            request_id = hex::encode(request.request_id),
+            batch_id = request.batch_id.map(hex::encode),
             payload = hex::encode(request.msg_hash),
             entropy = hex::encode(request.entropy),
+            block_height = request.block_height,
             "new sign request"
         );
         self.unorganized_requests.push(request);
     }
 
+    /// Records the height of the last indexed block, which decides whose turn it is to propose
+    /// each request.
+    pub fn set_block_height(&mut self, block_height: u64) {
+        self.block_height = self.block_height.max(block_height);
+    }
+
+    /// Returns the height of the last indexed block.
+    pub fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    /// Records a heartbeat received from another participant, along with the view it passed
+    /// on.
+    pub fn heard_from(&mut self, heartbeat: &HeartbeatMessage) {
+        self.liveness
+            .heard_from(heartbeat.from, heartbeat.block_height);
+        self.liveness.merge(&heartbeat.seen);
+    }
+
+    /// Records a heartbeat this node is sending, and returns the view to send along with it.
+    pub fn sending_heartbeat(&mut self, me: Participant) -> LivenessView {
+        self.liveness.heard_from(me, self.block_height);
+        self.liveness.view()
+    }
+
+    /// Returns whether it is time to send heartbeats to the other participants.
+    pub fn heartbeat_due(&mut self) -> bool {
+        self.liveness.heartbeat_due()
+    }
+
+    /// Removes a request once it has been served.
+    pub fn remove(&mut self, request_id: [u8; 32]) -> Option<SignRequest> {
+        if self.removed.len() >= MAX_REMOVED_REQUESTS {
+            self.removed.pop_front();
+        }
+        self.removed.push_back(request_id);
+        self.requests
+            .remove(&request_id)
+            .map(|queued| queued.request)
+    }
+
+    /// Returns the requests removed since the last call, so that the messages buffered for
+    /// them can be dropped.
+    pub fn take_removed(&mut self) -> Vec<[u8; 32]> {
+        self.removed.drain(..).collect()
+    }
+
     pub fn organize(&mut self, state: &RunningState, me: Participant) {
+        // Until then this node would take every participant it has not heard from itself for
+        // offline, so the requests wait.
+        if !self.liveness.is_settled() {
+            return;
+        }
+        let participants: Vec<Participant> = state.participants.keys().copied().collect();
         for request in self.unorganized_requests.drain(..) {
-            let mut rng = StdRng::from_seed(request.entropy);
-            let subset = state
-                .participants
-                .keys()
-                .choose_multiple(&mut rng, state.threshold);
-            let proposer = **subset.choose(&mut rng).unwrap();
-            if subset.contains(&&me) {
+            let Some(proposers) =
+                choose_proposers(&self.liveness, &participants, state.threshold, &request)
+            else {
+                tracing::warn!(
+                    receipt_id = %request.receipt_id,
+                    request_id = hex::encode(request.request_id),
+                    "skipping sign request: too few participants online to sign it"
+                );
+                continue;
+            };
+            if proposers.contains(&me) {
                 tracing::info!(
                     receipt_id = %request.receipt_id,
-                    ?subset,
-                    ?proposer,
+                    request_id = hex::encode(request.request_id),
+                    batch_id = request.batch_id.map(hex::encode),
+                    ?proposers,
                     "saving sign request: node is in the signer subset"
                 );
-                let proposer_requests = self.requests.entry(proposer).or_default();
-                proposer_requests.insert(request.receipt_id, request);
+                self.requests.insert(
+                    request.request_id,
+                    QueuedRequest {
+                        request,
+                        proposers,
+                        proposed: false,
+                    },
+                );
             } else {
                 tracing::info!(
                     receipt_id = %request.receipt_id,
-                    ?subset,
-                    ?proposer,
+                    request_id = hex::encode(request.request_id),
+                    batch_id = request.batch_id.map(hex::encode),
+                    ?proposers,
                     "skipping sign request: node is NOT in the signer subset"
                 );
             }
         }
     }
 
-    pub fn contains(&self, participant: Participant, receipt_id: CryptoHash) -> bool {
-        let Some(participant_requests) = self.requests.get(&participant) else {
-            return false;
-        };
-        participant_requests.contains_key(&receipt_id)
+    /// Returns the index of the proposer whose turn it is to propose the request.
+    fn turn(&self, queued: &QueuedRequest) -> usize {
+        let waited = self
+            .block_height
+            .saturating_sub(queued.request.block_height);
+        ((waited / self.failover_blocks) as usize).min(queued.proposers.len() - 1)
+    }
+
+    /// Returns the proposers whose turn to propose the request has come, the current one last.
+    fn active_proposers<'a>(&self, queued: &'a QueuedRequest) -> &'a [Participant] {
+        &queued.proposers[..=self.turn(queued)]
+    }
+
+    pub fn contains(&self, participant: Participant, request_id: [u8; 32]) -> bool {
+        self.get(participant, request_id).is_some()
+    }
+
+    /// Returns the request with the given id if `participant` is allowed to propose it, which
+    /// is the case once its turn has come.
+    pub fn get(&self, participant: Participant, request_id: [u8; 32]) -> Option<&SignRequest> {
+        let queued = self.requests.get(&request_id)?;
+        self.active_proposers(queued)
+            .contains(&participant)
+            .then_some(&queued.request)
     }
 
-    pub fn my_requests(&mut self, me: Participant) -> &mut HashMap<CryptoHash, SignRequest> {
-        self.requests.entry(me).or_default()
+    /// Whether this node is in the signer subset of any request that has not been served yet.
+    pub fn has_pending(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    /// Returns the ids of the requests it is this node's turn to propose and that it has not
+    /// proposed yet.
+    pub fn my_requests(&self, me: Participant) -> Vec<[u8; 32]> {
+        self.requests
+            .iter()
+            .filter(|(_, queued)| {
+                !queued.proposed && self.active_proposers(queued).last() == Some(&me)
+            })
+            .map(|(request_id, _)| *request_id)
+            .collect()
+    }
+
+    /// Marks the request as proposed by this node, so that it does not spend another
+    /// presignature on it while its protocol is running. Returns the request along with the
+    /// turn it is proposed at.
+    pub fn mark_proposed(&mut self, request_id: [u8; 32]) -> Option<(&SignRequest, usize)> {
+        let turn = self.turn(self.requests.get(&request_id)?);
+        let queued = self.requests.get_mut(&request_id)?;
+        queued.proposed = true;
+        if turn > 0 {
+            tracing::info!(
+                request_id = hex::encode(request_id),
+                turn,
+                "taking over sign request as a backup proposer"
+            );
+        }
+        Some((&queued.request, turn))
+    }
+
+    /// Lets this node propose the request again once its protocol for it has been evicted.
+    pub fn unmark_proposed(&mut self, request_id: [u8; 32]) {
+        if let Some(queued) = self.requests.get_mut(&request_id) {
+            queued.proposed = false;
+        }
     }
 }
 
+/// Chooses the signer subset of `request` among the participants that are online as of its
+/// block, every node included, and returns it in the order its members take turns proposing
+/// the request. Returns `None` if too few participants are online to sign it.
+fn choose_proposers(
+    liveness: &Liveness,
+    participants: &[Participant],
+    threshold: usize,
+    request: &SignRequest,
+) -> Option<Vec<Participant>> {
+    let mut rng = StdRng::from_seed(request.entropy);
+    let online: Vec<Participant> = participants
+        .iter()
+        .copied()
+        .filter(|p| liveness.is_online(*p, request.block_height))
+        .collect();
+    if online.len() < threshold {
+        return None;
+    }
+    let subset = online.into_iter().choose_multiple(&mut rng, threshold);
+    let proposer = *subset.choose(&mut rng).unwrap();
+    // The rest of the subset backs the proposer up, in an order every signer agrees on.
+    let mut backups: Vec<Participant> = subset.iter().copied().filter(|p| *p != proposer).collect();
+    backups.shuffle(&mut rng);
+    let mut proposers = vec![proposer];
+    proposers.extend(backups);
+    Some(proposers)
+}
+
 /// An ongoing signature generator.
 pub struct SignatureGenerator {
     pub protocol: SignatureProtocol,
+    pub receipt_id: CryptoHash,
     pub proposer: Participant,
+    /// The turn at which this node proposed the request, if it is the proposer.
+    pub turn: Option<usize>,
     pub presignature_id: PresignatureId,
+    pub request_id: [u8; 32],
     pub msg_hash: [u8; 32],
     pub epsilon: Scalar,
     pub delta: Scalar,
+    /// When the generation started.
+    pub started: Instant,
+    /// The last time the protocol made progress.
+    pub last_progress: Instant,
 }
 
 pub struct SignatureManager {
-    /// Ongoing signature generation protocols.
-    generators: HashMap<CryptoHash, SignatureGenerator>,
-    /// Generated signatures assigned to the current node that are yet to be published.
-    signatures: Vec<(CryptoHash, [u8; 32], FullSignature<Secp256k1>)>,
+    /// Ongoing signature generation protocols, by request id and proposer, as a backup
+    /// proposer may take over a request while the protocol of a previous one is running.
+    generators: HashMap<([u8; 32], Participant), SignatureGenerator>,
+    /// Generated signatures assigned to the current node that are yet to be published, along
+    /// with the turn they were proposed at.
+    signatures: Vec<(CryptoHash, [u8; 32], usize, FullSignature<Secp256k1>)>,
 
     participants: Vec<Participant>,
     me: Participant,
@@ -128,11 +329,14 @@ impl SignatureManager {
 
     #[allow(clippy::too_many_arguments)]
     fn generate_internal(
+        receipt_id: CryptoHash,
         participants: &[Participant],
         me: Participant,
         public_key: PublicKey,
         proposer: Participant,
+        turn: Option<usize>,
         presignature: Presignature,
+        request_id: [u8; 32],
         msg_hash: [u8; 32],
         epsilon: Scalar,
         delta: Scalar,
@@ -151,41 +355,104 @@ impl SignatureManager {
             output,
             Scalar::from_bytes(&msg_hash),
         )?);
+        let now = Instant::now();
         Ok(SignatureGenerator {
             protocol,
+            receipt_id,
             proposer,
+            turn,
             presignature_id: presignature.id,
+            request_id,
             msg_hash,
             epsilon,
             delta,
+            started: now,
+            last_progress: now,
         })
     }
 
     /// Starts a new presignature generation protocol.
+    #[allow(clippy::too_many_arguments)]
     pub fn generate(
         &mut self,
         receipt_id: CryptoHash,
+        turn: usize,
         presignature: Presignature,
         public_key: PublicKey,
+        request_id: [u8; 32],
         msg_hash: [u8; 32],
         epsilon: Scalar,
         delta: Scalar,
     ) -> Result<(), InitializationError> {
-        tracing::info!(%receipt_id, "starting protocol to generate a new signature");
+        tracing::info!(
+            %receipt_id,
+            request_id = hex::encode(request_id),
+            "starting protocol to generate a new signature"
+        );
         let generator = Self::generate_internal(
+            receipt_id,
             &self.participants,
             self.me,
             public_key,
             self.me,
+            Some(turn),
             presignature,
+            request_id,
             msg_hash,
             epsilon,
             delta,
         )?;
-        self.generators.insert(receipt_id, generator);
+        self.generators.insert((request_id, self.me), generator);
         Ok(())
     }
 
+    /// Drops the generators that have not made progress within `timeout`, most likely because
+    /// one of the participants went offline. Their presignatures are discarded, as the other
+    /// participants may have already used their shares of them. The request itself stays
+    /// pending on the contract. Returns the requests of the evicted generators this node
+    /// proposed, so that it can propose them again.
+    pub fn evict_stalled(&mut self, timeout: Duration) -> Vec<[u8; 32]> {
+        let mut mine = Vec::new();
+        self.generators.retain(|(request_id, _), generator| {
+            let stalled = generator.last_progress.elapsed() > timeout;
+            if stalled {
+                tracing::warn!(
+                    receipt_id = %generator.receipt_id,
+                    request_id = hex::encode(request_id),
+                    presignature_id = generator.presignature_id,
+                    proposer = ?generator.proposer,
+                    age = ?generator.started.elapsed(),
+                    idle = ?generator.last_progress.elapsed(),
+                    "evicting stalled signature generator, discarding its presignature"
+                );
+                metrics::GENERATOR_TIMEOUTS
+                    .with_label_values(&["signature"])
+                    .inc();
+                if generator.proposer == self.me {
+                    mine.push(*request_id);
+                }
+            }
+            !stalled
+        });
+        mine
+    }
+
+    /// Returns the ongoing generator for the given request proposed by `proposer`, if any.
+    pub fn generator(
+        &self,
+        request_id: [u8; 32],
+        proposer: Participant,
+    ) -> Option<&SignatureGenerator> {
+        self.generators.get(&(request_id, proposer))
+    }
+
+    /// Returns whether a protocol for the given request is running, whoever proposed it.
+    pub fn is_generating(&self, request_id: [u8; 32]) -> bool {
+        self.generators
+            .keys()
+            .any(|(generating, _)| *generating == request_id)
+    }
+
     /// Ensures that the presignature with the given id is either:
     /// 1) Already generated in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
@@ -198,24 +465,33 @@ impl SignatureManager {
         receipt_id: CryptoHash,
         proposer: Participant,
         presignature_id: PresignatureId,
+        request_id: [u8; 32],
         msg_hash: [u8; 32],
         epsilon: Scalar,
         delta: Scalar,
         presignature_manager: &mut PresignatureManager,
     ) -> Result<Option<&mut SignatureProtocol>, InitializationError> {
-        match self.generators.entry(receipt_id) {
+        match self.generators.entry((request_id, proposer)) {
             Entry::Vacant(entry) => {
-                tracing::info!(%receipt_id, "joining protocol to generate a new signature");
+                tracing::info!(
+                    %receipt_id,
+                    request_id = hex::encode(request_id),
+                    ?proposer,
+                    "joining protocol to generate a new signature"
+                );
                 let Some(presignature) = presignature_manager.take(presignature_id) else {
                     tracing::warn!(presignature_id, "presignature is missing, can't join");
                     return Ok(None);
                 };
                 let generator = Self::generate_internal(
+                    receipt_id,
                     &self.participants,
                     self.me,
                     self.public_key,
                     proposer,
+                    None,
                     presignature,
+                    request_id,
                     msg_hash,
                     epsilon,
                     delta,
@@ -234,7 +510,7 @@ impl SignatureManager {
     pub fn poke(&mut self) -> Result<Vec<(Participant, SignatureMessage)>, ProtocolError> {
         let mut messages = Vec::new();
         let mut result = Ok(());
-        self.generators.retain(|receipt_id, generator| {
+        self.generators.retain(|(request_id, _), generator| {
             loop {
                 let protocol = &mut generator.protocol;
                 let action = match protocol.poke() {
@@ -244,6 +520,9 @@ impl SignatureManager {
                         break false;
                     }
                 };
+                if !matches!(action, Action::Wait) {
+                    generator.last_progress = Instant::now();
+                }
                 match action {
                     Action::Wait => {
                         tracing::debug!("waiting");
@@ -255,9 +534,10 @@ impl SignatureManager {
                             messages.push((
                                 *p,
                                 SignatureMessage {
-                                    receipt_id: *receipt_id,
+                                    receipt_id: generator.receipt_id,
                                     proposer: generator.proposer,
                                     presignature_id: generator.presignature_id,
+                                    request_id: generator.request_id,
                                     msg_hash: generator.msg_hash,
                                     epsilon: generator.epsilon,
                                     delta: generator.delta,
@@ -271,9 +551,10 @@ impl SignatureManager {
                     Action::SendPrivate(p, data) => messages.push((
                         p,
                         SignatureMessage {
-                            receipt_id: *receipt_id,
+                            receipt_id: generator.receipt_id,
                             proposer: generator.proposer,
                             presignature_id: generator.presignature_id,
+                            request_id: generator.request_id,
                             msg_hash: generator.msg_hash,
                             epsilon: generator.epsilon,
                             delta: generator.delta,
@@ -284,14 +565,20 @@ impl SignatureManager {
                     )),
                     Action::Return(output) => {
                         tracing::info!(
-                            ?receipt_id,
+                            receipt_id = %generator.receipt_id,
+                            request_id = hex::encode(request_id),
                             big_r = ?output.big_r.to_base58(),
                             s = ?output.s,
+                            elapsed = ?generator.started.elapsed(),
                             "completed signature generation"
                         );
                         if generator.proposer == self.me {
-                            self.signatures
-                                .push((*receipt_id, generator.msg_hash, output));
+                            self.signatures.push((
+                                generator.receipt_id,
+                                *request_id,
+                                generator.turn.unwrap_or_default(),
+                                output,
+                            ));
                         }
                         // Do not retain the protocol
                         return false;
@@ -308,14 +595,7 @@ impl SignatureManager {
         signer: &T,
         mpc_contract_id: &AccountId,
     ) -> Result<(), near_fetch::Error> {
-        for (receipt_id, payload, signature) in self.signatures.drain(..) {
-            // TODO: Figure out how to properly serialize the signature
-            // let r_s = signature.big_r.x().concat(signature.s.to_bytes());
-            // let tag =
-            //     ConditionallySelectable::conditional_select(&2u8, &3u8, signature.big_r.y_is_odd());
-            // let signature = r_s.append(tag);
-            // let signature = Secp256K1Signature::try_from(signature.as_slice()).unwrap();
-            // let signature = Signature::SECP256K1(signature);
+        for (receipt_id, request_id, turn, signature) in self.signatures.drain(..) {
             let response = rpc_client
                 .send_tx(
                     signer,
@@ -324,9 +604,8 @@ impl SignatureManager {
                         FunctionCallAction {
                             method_name: "respond".to_string(),
                             args: serde_json::to_vec(&serde_json::json!({
-                                "payload": payload,
-                                "big_r": signature.big_r,
-                                "s": signature.s
+                                "request_id": request_id,
+                                "signature": to_signature_response(&signature),
                             }))
                             .unwrap(),
                             gas: 300_000_000_000_000,
@@ -335,8 +614,146 @@ impl SignatureManager {
                     )],
                 )
                 .await?;
-            tracing::info!(%receipt_id, big_r = signature.big_r.to_base58(), s = ?signature.s, status = ?response.status, "published signature response");
+            metrics::SIGNATURES_PUBLISHED
+                .with_label_values(&[&turn.to_string()])
+                .inc();
+            tracing::info!(%receipt_id, request_id = hex::encode(request_id), turn, big_r = signature.big_r.to_base58(), s = ?signature.s, status = ?response.status, "published signature response");
         }
         Ok(())
     }
 }
+
+/// Converts the signature into the recoverable form the contract expects, flipping `s` into
+/// the lower half of the curve order if needed. Flipping `s` is the same as negating `R`, so
+/// the parity of its y coordinate flips along with it.
+fn to_signature_response(signature: &FullSignature<Secp256k1>) -> SignatureResponse {
+    let mut s = signature.s;
+    let mut v = u8::from(bool::from(signature.big_r.y_is_odd()));
+    if bool::from(s.is_high()) {
+        s = -s;
+        v ^= 1;
+    }
+    SignatureResponse {
+        r: signature.big_r.x().into(),
+        s: s.to_bytes().into(),
+        v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose_proposers, Liveness, QueuedRequest, SignQueue, SignRequest};
+    use cait_sith::protocol::Participant;
+    use k256::Scalar;
+    use near_primitives::hash::CryptoHash;
+
+    fn request(request_id: [u8; 32], block_height: u64) -> SignRequest {
+        SignRequest {
+            receipt_id: CryptoHash::default(),
+            request_id,
+            batch_id: None,
+            msg_hash: [0; 32],
+            epsilon: Scalar::ONE,
+            delta: Scalar::ONE,
+            entropy: request_id,
+            block_height,
+        }
+    }
+
+    fn queue_with_request(failover_blocks: u64, request_id: [u8; 32]) -> SignQueue {
+        let mut queue = SignQueue::new(failover_blocks);
+        queue.requests.insert(
+            request_id,
+            QueuedRequest {
+                request: request(request_id, 100),
+                proposers: (0..3u32).map(Participant::from).collect(),
+                proposed: false,
+            },
+        );
+        queue
+    }
+
+    #[test]
+    fn every_node_is_judged_by_its_heartbeats_alike() {
+        let participants: Vec<Participant> = (0..4u32).map(Participant::from).collect();
+        // Participant 3 has not sent a heartbeat in the window requests at block 150 use, not
+        // even to itself.
+        let mut liveness = Liveness::default();
+        for (p, block_height) in [(0, 70), (1, 75), (2, 80), (3, 10)] {
+            liveness.heard_from(participants[p], block_height);
+        }
+        // A node that restarted since has the same view once its peers passed theirs on.
+        let mut restarted = Liveness::default();
+        restarted.merge(&liveness.view());
+
+        for i in 0..16u8 {
+            let request = request([i; 32], 150);
+            let proposers = choose_proposers(&liveness, &participants, 3, &request).unwrap();
+            assert_eq!(proposers.len(), 3);
+            assert!(!proposers.contains(&participants[3]));
+            assert_eq!(
+                choose_proposers(&restarted, &participants, 3, &request),
+                Some(proposers)
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_not_assigned_when_too_few_are_online() {
+        let participants: Vec<Participant> = (0..3u32).map(Participant::from).collect();
+        let mut liveness = Liveness::default();
+        liveness.heard_from(participants[0], 70);
+        liveness.heard_from(participants[1], 70);
+
+        let request = request([1; 32], 150);
+        assert!(choose_proposers(&liveness, &participants, 2, &request).is_some());
+        assert!(choose_proposers(&liveness, &participants, 3, &request).is_none());
+    }
+
+    #[test]
+    fn proposers_take_turns_until_the_last_one() {
+        let request_id = [1; 32];
+        let [p0, p1, p2] = [0u32, 1, 2].map(Participant::from);
+        let mut queue = queue_with_request(10, request_id);
+
+        queue.set_block_height(109);
+        assert_eq!(queue.my_requests(p0), vec![request_id]);
+        assert!(queue.my_requests(p1).is_empty());
+        assert!(queue.contains(p0, request_id));
+        assert!(!queue.contains(p1, request_id));
+
+        // The next proposer takes over, the previous one's messages are still accepted.
+        queue.set_block_height(110);
+        assert!(queue.my_requests(p0).is_empty());
+        assert_eq!(queue.my_requests(p1), vec![request_id]);
+        assert!(queue.contains(p0, request_id));
+        assert!(queue.contains(p1, request_id));
+        assert!(!queue.contains(p2, request_id));
+
+        // The last proposer keeps its turn, and the block height never goes back.
+        queue.set_block_height(1000);
+        queue.set_block_height(105);
+        assert_eq!(queue.my_requests(p2), vec![request_id]);
+        assert!(queue.contains(p2, request_id));
+    }
+
+    #[test]
+    fn requests_are_proposed_again_once_evicted() {
+        let request_id = [1; 32];
+        let p0 = Participant::from(0u32);
+        let mut queue = queue_with_request(10, request_id);
+        queue.set_block_height(100);
+
+        let (_, turn) = queue.mark_proposed(request_id).unwrap();
+        assert_eq!(turn, 0);
+        assert!(queue.my_requests(p0).is_empty());
+        queue.unmark_proposed(request_id);
+        assert_eq!(queue.my_requests(p0), vec![request_id]);
+
+        queue.set_block_height(125);
+        assert_eq!(queue.mark_proposed(request_id).unwrap().1, 2);
+        assert!(queue.remove(request_id).is_some());
+        assert!(queue.mark_proposed(request_id).is_none());
+        assert_eq!(queue.take_removed(), vec![request_id]);
+    }
+}
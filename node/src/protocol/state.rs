@@ -46,6 +46,9 @@ pub struct WaitingForConsensusState {
     pub threshold: usize,
     pub private_share: SecretKeyShare,
     pub public_key: PublicKey,
+    /// The share held before resharing, kept until the contract confirms the new epoch in
+    /// case the resharing gets cancelled.
+    pub old_private_share: Option<SecretKeyShare>,
     pub messages: Arc<RwLock<MessageQueue>>,
 }
 
@@ -65,6 +68,9 @@ pub struct RunningState {
     pub threshold: usize,
     pub private_share: SecretKeyShare,
     pub public_key: PublicKey,
+    /// Whether the contract is paused. No new triples or presignatures are started while
+    /// paused, but the ones in flight are still finished and queued requests are still signed.
+    pub paused: bool,
     pub sign_queue: Arc<RwLock<SignQueue>>,
     pub triple_manager: Arc<RwLock<TripleManager>>,
     pub presignature_manager: Arc<RwLock<PresignatureManager>>,
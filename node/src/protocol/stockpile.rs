@@ -0,0 +1,219 @@
+//! Settings for how many triples and presignatures a node keeps in stock, and how fast it
+//! generates new ones depending on the number of sign requests waiting for it.
+
+use std::time::Duration;
+
+const DEFAULT_MIN_TRIPLES: usize = 10;
+const DEFAULT_MAX_TRIPLES: usize = 100;
+const DEFAULT_MAX_CONCURRENT_TRIPLE_GENERATORS: usize = 16;
+const DEFAULT_TARGET_TRIPLES_PER_NODE: usize = 4;
+const DEFAULT_MIN_PRESIGNATURES: usize = 2;
+const DEFAULT_MAX_PRESIGNATURES: usize = 50;
+const DEFAULT_MAX_CONCURRENT_PRESIGNATURE_GENERATORS: usize = 8;
+const DEFAULT_TARGET_PRESIGNATURES_PER_NODE: usize = 2;
+const DEFAULT_GENERATOR_TIMEOUT_SECS: u64 = 120;
+
+/// Limits on the stock of one kind of item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StockpileConfig {
+    /// Total stock, including items being generated, below which generation runs at full
+    /// speed even when idle.
+    pub min_stock: usize,
+    /// Total stock, including items being generated, that is never exceeded.
+    pub max_stock: usize,
+    /// Maximum number of generation protocols running at once.
+    pub max_concurrent_generators: usize,
+    /// Number of items assigned to this node that it aims to hold when idle.
+    pub target_per_node: usize,
+}
+
+impl StockpileConfig {
+    /// Returns how many generation protocols to start now.
+    ///
+    /// * `my_stock` - items assigned to this node, including the ones it is generating.
+    /// * `total_stock` - all items known to this node, including the ones being generated.
+    /// * `generating` - ongoing generation protocols.
+    /// * `backlog` - items this node needs on top of its target to serve waiting requests.
+    ///
+    /// When idle at most one protocol is started per call, while a backlog starts as many as
+    /// are missing, within the limits.
+    pub fn to_generate(
+        &self,
+        my_stock: usize,
+        total_stock: usize,
+        generating: usize,
+        backlog: usize,
+    ) -> usize {
+        let room = self
+            .max_stock
+            .saturating_sub(total_stock)
+            .min(self.max_concurrent_generators.saturating_sub(generating));
+        if total_stock < self.min_stock {
+            return room.min(self.min_stock - total_stock);
+        }
+        let wanted = self.target_per_node + backlog;
+        if my_stock >= wanted {
+            return 0;
+        }
+        let batch = if backlog == 0 { 1 } else { wanted - my_stock };
+        room.min(batch)
+    }
+}
+
+/// Configures the triple and presignature stockpiles.
+#[derive(Debug, Clone, clap::Parser)]
+#[group(id = "stockpile_options")]
+pub struct Options {
+    /// Minimum number of triples to keep in stock.
+    #[clap(long, env("MPC_RECOVERY_MIN_TRIPLES"), default_value_t = DEFAULT_MIN_TRIPLES)]
+    pub min_triples: usize,
+    /// Maximum number of triples to keep in stock.
+    #[clap(long, env("MPC_RECOVERY_MAX_TRIPLES"), default_value_t = DEFAULT_MAX_TRIPLES)]
+    pub max_triples: usize,
+    /// Maximum number of triples generated at once.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_MAX_CONCURRENT_TRIPLE_GENERATORS"),
+        default_value_t = DEFAULT_MAX_CONCURRENT_TRIPLE_GENERATORS
+    )]
+    pub max_concurrent_triple_generators: usize,
+    /// Number of triples assigned to this node to keep in stock when idle.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_TARGET_TRIPLES_PER_NODE"),
+        default_value_t = DEFAULT_TARGET_TRIPLES_PER_NODE
+    )]
+    pub target_triples_per_node: usize,
+    /// Minimum number of presignatures to keep in stock.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_MIN_PRESIGNATURES"),
+        default_value_t = DEFAULT_MIN_PRESIGNATURES
+    )]
+    pub min_presignatures: usize,
+    /// Maximum number of presignatures to keep in stock.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_MAX_PRESIGNATURES"),
+        default_value_t = DEFAULT_MAX_PRESIGNATURES
+    )]
+    pub max_presignatures: usize,
+    /// Maximum number of presignatures generated at once.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_MAX_CONCURRENT_PRESIGNATURE_GENERATORS"),
+        default_value_t = DEFAULT_MAX_CONCURRENT_PRESIGNATURE_GENERATORS
+    )]
+    pub max_concurrent_presignature_generators: usize,
+    /// Number of presignatures assigned to this node to keep in stock when idle.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_TARGET_PRESIGNATURES_PER_NODE"),
+        default_value_t = DEFAULT_TARGET_PRESIGNATURES_PER_NODE
+    )]
+    pub target_presignatures_per_node: usize,
+    /// Seconds after which a triple, presignature or signature generator that made no
+    /// progress is dropped.
+    #[clap(
+        long,
+        env("MPC_RECOVERY_GENERATOR_TIMEOUT_SECS"),
+        default_value_t = DEFAULT_GENERATOR_TIMEOUT_SECS
+    )]
+    pub generator_timeout_secs: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            min_triples: DEFAULT_MIN_TRIPLES,
+            max_triples: DEFAULT_MAX_TRIPLES,
+            max_concurrent_triple_generators: DEFAULT_MAX_CONCURRENT_TRIPLE_GENERATORS,
+            target_triples_per_node: DEFAULT_TARGET_TRIPLES_PER_NODE,
+            min_presignatures: DEFAULT_MIN_PRESIGNATURES,
+            max_presignatures: DEFAULT_MAX_PRESIGNATURES,
+            max_concurrent_presignature_generators: DEFAULT_MAX_CONCURRENT_PRESIGNATURE_GENERATORS,
+            target_presignatures_per_node: DEFAULT_TARGET_PRESIGNATURES_PER_NODE,
+            generator_timeout_secs: DEFAULT_GENERATOR_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        vec![
+            "--min-triples".to_string(),
+            self.min_triples.to_string(),
+            "--max-triples".to_string(),
+            self.max_triples.to_string(),
+            "--max-concurrent-triple-generators".to_string(),
+            self.max_concurrent_triple_generators.to_string(),
+            "--target-triples-per-node".to_string(),
+            self.target_triples_per_node.to_string(),
+            "--min-presignatures".to_string(),
+            self.min_presignatures.to_string(),
+            "--max-presignatures".to_string(),
+            self.max_presignatures.to_string(),
+            "--max-concurrent-presignature-generators".to_string(),
+            self.max_concurrent_presignature_generators.to_string(),
+            "--target-presignatures-per-node".to_string(),
+            self.target_presignatures_per_node.to_string(),
+            "--generator-timeout-secs".to_string(),
+            self.generator_timeout_secs.to_string(),
+        ]
+    }
+
+    pub fn generator_timeout(&self) -> Duration {
+        Duration::from_secs(self.generator_timeout_secs)
+    }
+
+    pub fn triple_config(&self) -> StockpileConfig {
+        StockpileConfig {
+            min_stock: self.min_triples,
+            max_stock: self.max_triples,
+            max_concurrent_generators: self.max_concurrent_triple_generators,
+            target_per_node: self.target_triples_per_node,
+        }
+    }
+
+    pub fn presignature_config(&self) -> StockpileConfig {
+        StockpileConfig {
+            min_stock: self.min_presignatures,
+            max_stock: self.max_presignatures,
+            max_concurrent_generators: self.max_concurrent_presignature_generators,
+            target_per_node: self.target_presignatures_per_node,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StockpileConfig;
+
+    const CONFIG: StockpileConfig = StockpileConfig {
+        min_stock: 4,
+        max_stock: 20,
+        max_concurrent_generators: 5,
+        target_per_node: 2,
+    };
+
+    #[test]
+    fn fills_up_to_min_stock() {
+        assert_eq!(CONFIG.to_generate(0, 0, 0, 0), 4);
+        assert_eq!(CONFIG.to_generate(0, 2, 2, 0), 2);
+        assert_eq!(CONFIG.to_generate(0, 3, 5, 0), 0);
+    }
+
+    #[test]
+    fn generates_slowly_when_idle() {
+        assert_eq!(CONFIG.to_generate(0, 10, 0, 0), 1);
+        assert_eq!(CONFIG.to_generate(2, 10, 0, 0), 0);
+    }
+
+    #[test]
+    fn speeds_up_with_backlog() {
+        assert_eq!(CONFIG.to_generate(2, 10, 0, 3), 3);
+        assert_eq!(CONFIG.to_generate(0, 10, 2, 10), 3);
+        assert_eq!(CONFIG.to_generate(0, 19, 0, 10), 1);
+        assert_eq!(CONFIG.to_generate(0, 20, 0, 10), 0);
+    }
+}
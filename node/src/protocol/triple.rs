@@ -1,5 +1,8 @@
 use super::cryptography::CryptographicError;
+use super::evicted::Evicted;
 use super::message::TripleMessage;
+use crate::metrics;
+use crate::storage::{StockpileKind, StockpileStorageBox};
 use crate::types::TripleProtocol;
 use crate::util::AffinePointExt;
 use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
@@ -7,8 +10,10 @@ use cait_sith::triples::{TriplePub, TripleShare};
 use highway::{HighwayHash, HighwayHasher};
 use k256::elliptic_curve::group::GroupEncoding;
 use k256::Secp256k1;
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 /// Unique number used to identify a specific ongoing triple generation protocol.
 /// Without `TripleId` it would be unclear where to route incoming cait-sith triple generation
@@ -16,19 +21,46 @@ use std::collections::{HashMap, VecDeque};
 pub type TripleId = u64;
 
 /// A completed triple.
+#[derive(Serialize, Deserialize)]
 pub struct Triple {
     pub id: TripleId,
     pub share: TripleShare<Secp256k1>,
     pub public: TriplePub<Secp256k1>,
 }
 
+/// An ongoing triple generator.
+pub struct TripleGenerator {
+    pub protocol: TripleProtocol,
+    /// Whether this node initiated the generation.
+    pub mine: bool,
+    /// When the generation started.
+    pub started: Instant,
+    /// The last time the protocol made progress.
+    pub last_progress: Instant,
+}
+
+impl TripleGenerator {
+    fn new(protocol: TripleProtocol, mine: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            protocol,
+            mine,
+            started: now,
+            last_progress: now,
+        }
+    }
+}
+
 /// Abstracts how triples are generated by providing a way to request a new triple that will be
 /// complete some time in the future and a way to take an already generated triple.
 pub struct TripleManager {
     /// Completed unspent triples
     pub triples: HashMap<TripleId, Triple>,
     /// Ongoing triple generation protocols
-    pub generators: HashMap<TripleId, TripleProtocol>,
+    pub generators: HashMap<TripleId, TripleGenerator>,
+    /// Triples of this epoch whose generation was evicted, so that late messages do not start
+    /// them again.
+    pub evicted: Evicted<TripleId>,
     /// List of triple ids generation of which was initiated by the current node.
     pub mine: VecDeque<TripleId>,
 
@@ -36,23 +68,60 @@ pub struct TripleManager {
     pub me: Participant,
     pub threshold: usize,
     pub epoch: u64,
+    /// Keeps unspent triples around across restarts.
+    pub storage: StockpileStorageBox,
 }
 
 impl TripleManager {
+    /// Creates a manager holding the unspent triples of `epoch` kept in `storage`.
     pub fn new(
         participants: Vec<Participant>,
         me: Participant,
         threshold: usize,
         epoch: u64,
+        storage: StockpileStorageBox,
     ) -> Self {
+        let mut triples = HashMap::new();
+        let mut mine = VecDeque::new();
+        match storage.load(StockpileKind::Triple, epoch) {
+            Ok(stored) => {
+                for data in stored {
+                    match serde_json::from_slice::<(Triple, bool)>(&data) {
+                        Ok((triple, triple_is_mine)) => {
+                            if triple_is_mine {
+                                mine.push_back(triple.id);
+                            }
+                            triples.insert(triple.id, triple);
+                        }
+                        Err(err) => tracing::error!(?err, "failed to parse a stored triple"),
+                    }
+                }
+                tracing::info!(epoch, loaded = triples.len(), "loaded stored triples");
+            }
+            Err(err) => tracing::error!(?err, epoch, "failed to load stored triples"),
+        }
         Self {
-            triples: HashMap::new(),
+            triples,
             generators: HashMap::new(),
-            mine: VecDeque::new(),
+            evicted: Evicted::default(),
+            mine,
             participants,
             me,
             threshold,
             epoch,
+            storage,
+        }
+    }
+
+    /// Removes a triple from storage as it is being spent. If this fails the triple will be
+    /// loaded again after a restart, so the caller must not spend it.
+    fn forget(&self, id: TripleId) -> bool {
+        match self.storage.remove(StockpileKind::Triple, self.epoch, id) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::error!(?err, id, "failed to remove a spent triple from storage");
+                false
+            }
         }
     }
 
@@ -72,6 +141,17 @@ impl TripleManager {
         self.len() + self.generators.len()
     }
 
+    /// Returns the number of unspent triples assigned to this node plus the ongoing generation
+    /// protocols this node initiated.
+    pub fn my_potential_len(&self) -> usize {
+        self.my_len() + self.generators.values().filter(|g| g.mine).count()
+    }
+
+    /// Returns the number of ongoing generation protocols.
+    pub fn generating_len(&self) -> usize {
+        self.generators.len()
+    }
+
     /// Starts a new Beaver triple generation protocol.
     pub fn generate(&mut self) -> Result<(), InitializationError> {
         let id = rand::random();
@@ -81,10 +161,34 @@ impl TripleManager {
             self.me,
             self.threshold,
         )?);
-        self.generators.insert(id, protocol);
+        self.generators
+            .insert(id, TripleGenerator::new(protocol, true));
         Ok(())
     }
 
+    /// Drops the generators that have not made progress within `timeout`, most likely because
+    /// one of the participants went offline. Triple generation takes no inputs, so there is
+    /// nothing to release.
+    pub fn evict_stalled(&mut self, timeout: Duration) {
+        self.evicted.prune();
+        self.generators.retain(|id, generator| {
+            let stalled = generator.last_progress.elapsed() > timeout;
+            if stalled {
+                tracing::warn!(
+                    id,
+                    age = ?generator.started.elapsed(),
+                    idle = ?generator.last_progress.elapsed(),
+                    "evicting stalled triple generator"
+                );
+                metrics::GENERATOR_TIMEOUTS
+                    .with_label_values(&["triple"])
+                    .inc();
+                self.evicted.insert(*id);
+            }
+            !stalled
+        });
+    }
+
     /// Take two unspent triple by theirs id with no way to return it. Only takes
     /// if both of them are present.
     /// It is very important to NOT reuse the same triple twice for two different
@@ -95,10 +199,17 @@ impl TripleManager {
         } else if !self.triples.contains_key(&id1) {
             Err(id1)
         } else {
-            Ok((
-                self.triples.remove(&id0).unwrap(),
-                self.triples.remove(&id1).unwrap(),
-            ))
+            let triple0 = self.triples.remove(&id0).unwrap();
+            let triple1 = self.triples.remove(&id1).unwrap();
+            // Both triples are dropped if either of them can't be removed from storage, as
+            // they would otherwise be spent again after a restart.
+            if !self.forget(id0) {
+                Err(id0)
+            } else if !self.forget(id1) {
+                Err(id1)
+            } else {
+                Ok((triple0, triple1))
+            }
         }
     }
 
@@ -121,15 +232,17 @@ impl TripleManager {
     }
 
     /// Ensures that the triple with the given id is either:
-    /// 1) Already generated in which case returns `None`, or
+    /// 1) Already generated or evicted in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
     /// 3) Has never been seen by the manager in which case start a new protocol and returns `Some(protocol)`
+    ///
+    /// This is called as messages for the triple arrive, so it counts as progress.
     // TODO: What if the triple completed generation and is already spent?
     pub fn get_or_generate(
         &mut self,
         id: TripleId,
     ) -> Result<Option<&mut TripleProtocol>, CryptographicError> {
-        if self.triples.contains_key(&id) {
+        if self.triples.contains_key(&id) || self.evicted.contains(&id) {
             Ok(None)
         } else {
             match self.generators.entry(id) {
@@ -140,10 +253,14 @@ impl TripleManager {
                         self.me,
                         self.threshold,
                     )?);
-                    let generator = e.insert(protocol);
-                    Ok(Some(generator))
+                    let generator = e.insert(TripleGenerator::new(protocol, false));
+                    Ok(Some(&mut generator.protocol))
+                }
+                Entry::Occupied(e) => {
+                    let generator = e.into_mut();
+                    generator.last_progress = Instant::now();
+                    Ok(Some(&mut generator.protocol))
                 }
-                Entry::Occupied(e) => Ok(Some(e.into_mut())),
             }
         }
     }
@@ -155,15 +272,18 @@ impl TripleManager {
     pub fn poke(&mut self) -> Result<Vec<(Participant, TripleMessage)>, ProtocolError> {
         let mut messages = Vec::new();
         let mut result = Ok(());
-        self.generators.retain(|id, protocol| {
+        self.generators.retain(|id, generator| {
             loop {
-                let action = match protocol.poke() {
+                let action = match generator.protocol.poke() {
                     Ok(action) => action,
                     Err(e) => {
                         result = Err(e);
                         break false;
                     }
                 };
+                if !matches!(action, Action::Wait) {
+                    generator.last_progress = Instant::now();
+                }
 
                 match action {
                     Action::Wait => {
@@ -199,6 +319,7 @@ impl TripleManager {
                             big_a = ?output.1.big_a.to_base58(),
                             big_b = ?output.1.big_b.to_base58(),
                             big_c = ?output.1.big_c.to_base58(),
+                            elapsed = ?generator.started.elapsed(),
                             "completed triple generation"
                         );
 
@@ -231,6 +352,21 @@ impl TripleManager {
                             self.mine.push_back(*id);
                         }
 
+                        // A triple that could not be stored is still usable until a restart.
+                        match serde_json::to_vec(&(&triple, triple_is_mine)) {
+                            Ok(data) => {
+                                if let Err(err) = self.storage.store(
+                                    StockpileKind::Triple,
+                                    self.epoch,
+                                    *id,
+                                    &data,
+                                ) {
+                                    tracing::warn!(?err, id, "failed to store a triple");
+                                }
+                            }
+                            Err(err) => tracing::warn!(?err, id, "failed to serialize a triple"),
+                        }
+
                         self.triples.insert(*id, triple);
 
                         // Do not retain the protocol
@@ -248,9 +384,11 @@ mod test {
     use std::{collections::HashMap, fs::OpenOptions, ops::Range};
 
     use crate::protocol::message::TripleMessage;
+    use crate::storage::stockpile;
     use cait_sith::protocol::{InitializationError, Participant, ProtocolError};
     use itertools::multiunzip;
     use std::io::prelude::*;
+    use std::time::Duration;
 
     use super::TripleManager;
 
@@ -265,7 +403,15 @@ mod test {
             let participants: Vec<Participant> = range.map(Participant::from).collect();
             let managers = participants
                 .iter()
-                .map(|me| TripleManager::new(participants.clone(), *me, number as usize, 0))
+                .map(|me| {
+                    TripleManager::new(
+                        participants.clone(),
+                        *me,
+                        number as usize,
+                        0,
+                        stockpile::memory(),
+                    )
+                })
                 .collect();
             TestManagers { managers }
         }
@@ -404,4 +550,83 @@ mod test {
             "All triple IDs and public parts are identical"
         )
     }
+
+    #[test]
+    fn stored_triples_are_reloaded_unless_spent() {
+        let mut tm = TestManagers::new(3);
+        tm.generate(0).unwrap();
+        tm.generate(1).unwrap();
+        tm.poke_until_quiet().unwrap();
+
+        let reload = |manager: &TripleManager| {
+            TripleManager::new(
+                manager.participants.clone(),
+                manager.me,
+                manager.threshold,
+                manager.epoch,
+                manager.storage.clone(),
+            )
+        };
+        let mut reloaded = reload(&tm.managers[0]);
+        assert_eq!(reloaded.len(), 2, "All triples should be reloaded");
+        assert_eq!(reloaded.my_len(), tm.managers[0].my_len());
+        let ids: Vec<_> = reloaded.triples.keys().copied().collect();
+        reloaded.take_two(ids[0], ids[1]).unwrap();
+
+        let mut reloaded = reload(&tm.managers[0]);
+        assert_eq!(reloaded.len(), 0, "Spent triples should not be reloaded");
+        assert_eq!(reloaded.take_two(ids[0], ids[1]).err(), Some(ids[0]));
+
+        // Triples of another epoch can not be used anymore.
+        let manager = &tm.managers[1];
+        let next_epoch = TripleManager::new(
+            manager.participants.clone(),
+            manager.me,
+            manager.threshold,
+            manager.epoch + 1,
+            manager.storage.clone(),
+        );
+        assert_eq!(next_epoch.len(), 0);
+    }
+
+    #[test]
+    fn my_potential_len_counts_the_generations_i_started() {
+        let mut tm = TestManagers::new(3);
+        tm.generate(0).unwrap();
+        tm.generate(0).unwrap();
+        tm.generate(1).unwrap();
+        assert_eq!(tm.managers[0].my_potential_len(), 2);
+        assert_eq!(tm.managers[1].my_potential_len(), 1);
+        assert_eq!(tm.managers[2].my_potential_len(), 0);
+
+        tm.poke_until_quiet().unwrap();
+        // Joined generations do not count towards our own stock.
+        for manager in &tm.managers {
+            assert_eq!(manager.generating_len(), 0);
+            assert_eq!(manager.my_potential_len(), manager.my_len());
+        }
+    }
+
+    #[test]
+    fn evicted_triples_are_not_generated_again() {
+        let mut tm = TestManagers::new(3);
+        tm.generate(0).unwrap();
+        tm.poke(0).unwrap();
+        let id = *tm.managers[1].generators.keys().next().unwrap();
+
+        // A message for the triple counts as progress.
+        let before = tm.managers[1].generators[&id].last_progress;
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(tm.managers[1].get_or_generate(id).unwrap().is_some());
+        assert!(tm.managers[1].generators[&id].last_progress > before);
+
+        std::thread::sleep(Duration::from_millis(1));
+        tm.managers[1].evict_stalled(Duration::ZERO);
+        assert_eq!(tm.managers[1].generating_len(), 0);
+        assert!(tm.managers[1].evicted.contains(&id));
+
+        // Late messages for the evicted triple are dropped instead of starting it over.
+        assert!(tm.managers[1].get_or_generate(id).unwrap().is_none());
+        assert_eq!(tm.managers[1].generating_len(), 0);
+    }
 }
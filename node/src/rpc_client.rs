@@ -16,6 +16,14 @@ pub async fn fetch_mpc_contract_state(
         .map_err(|_| anyhow::anyhow!("protocol state has not been initialized yet"))
 }
 
+/// Fetches the hash of the contract's protocol state, which only changes along with it.
+pub async fn fetch_mpc_contract_state_hash(
+    rpc_client: &near_fetch::Client,
+    mpc_contract_id: &AccountId,
+) -> anyhow::Result<[u8; 32]> {
+    Ok(rpc_client.view(mpc_contract_id, "state_hash", ()).await?)
+}
+
 pub async fn vote_for_public_key(
     rpc_client: &near_fetch::Client,
     signer: &InMemorySigner,
@@ -72,3 +80,49 @@ pub async fn vote_reshared(
         status => anyhow::bail!("unexpected status: {:?}", status),
     }
 }
+
+pub async fn vote_cancel_resharing(
+    rpc_client: &near_fetch::Client,
+    signer: &InMemorySigner,
+    mpc_contract_id: &AccountId,
+    epoch: u64,
+) -> anyhow::Result<bool> {
+    let args = json!({
+        "epoch": epoch
+    });
+    let result = rpc_client
+        .send_tx(
+            signer,
+            mpc_contract_id,
+            vec![Action::FunctionCall(FunctionCallAction {
+                method_name: "vote_cancel_resharing".to_string(),
+                args: serde_json::to_vec(&args)?,
+                gas: 300_000_000_000_000,
+                deposit: 0,
+            })],
+        )
+        .await?;
+
+    match result.status {
+        FinalExecutionStatus::SuccessValue(value) => Ok(serde_json::from_slice(&value)?),
+        status => anyhow::bail!("unexpected status: {:?}", status),
+    }
+}
+
+pub async fn fetch_resharing_expired(
+    rpc_client: &near_fetch::Client,
+    mpc_contract_id: &AccountId,
+) -> anyhow::Result<bool> {
+    Ok(rpc_client
+        .view(mpc_contract_id, "resharing_expired", ())
+        .await?)
+}
+
+/// Fetches the deposit the contract requires for `join`, in yoctoNEAR.
+pub async fn fetch_join_deposit(
+    rpc_client: &near_fetch::Client,
+    mpc_contract_id: &AccountId,
+) -> anyhow::Result<u128> {
+    let deposit: near_sdk::NearToken = rpc_client.view(mpc_contract_id, "join_deposit", ()).await?;
+    Ok(deposit.as_yoctonear())
+}
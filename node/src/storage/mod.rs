@@ -10,6 +10,7 @@ use google_secretmanager1::{
     },
     SecretManager,
 };
+use std::path::PathBuf;
 
 #[derive(thiserror::Error, Debug)]
 pub enum SecretStorageError {
@@ -19,8 +20,14 @@ pub enum SecretStorageError {
     IoError(#[from] std::io::Error),
     #[error("(de)serialization error: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("encryption error: {0}")]
+    Encryption(String),
 }
 
+pub mod stockpile;
+
+pub use stockpile::{StockpileKind, StockpileStorage, StockpileStorageBox};
+
 type Result<T> = std::result::Result<T, SecretStorageError>;
 
 #[async_trait]
@@ -132,6 +139,10 @@ pub struct Options {
     /// GCP Secret Manager ID that will be used to load/store the node's secret key share.
     #[clap(long, env("MPC_RECOVERY_SK_SHARE_SECRET_ID"), requires_all=["gcp_project_id"])]
     pub sk_share_secret_id: Option<String>,
+    /// Directory to keep the node's unspent triples and presignatures in, encrypted with its
+    /// cipher key, so that they survive restarts. They are kept in memory only if not set.
+    #[clap(long, env("MPC_RECOVERY_STOCKPILE_DIR"))]
+    pub stockpile_dir: Option<PathBuf>,
 }
 
 impl Options {
@@ -144,6 +155,12 @@ impl Options {
         if let Some(sk_share_secret_id) = self.sk_share_secret_id {
             opts.extend(vec!["--sk-share-secret-id".to_string(), sk_share_secret_id]);
         }
+        if let Some(stockpile_dir) = self.stockpile_dir {
+            opts.extend(vec![
+                "--stockpile-dir".to_string(),
+                stockpile_dir.display().to_string(),
+            ]);
+        }
 
         opts
     }
@@ -0,0 +1,366 @@
+//! Storage for the triples and presignatures a node has generated but not spent yet, so that
+//! they survive restarts. Every stored item is bound to the epoch it was generated in, and
+//! items of any other epoch are dropped on load since they can no longer be used.
+
+use super::{Options, Result, SecretStorageError};
+use mpc_keys::hpke;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Appended to the name of a file whose item can not be read, so that it is kept around for
+/// inspection but no longer loaded.
+const QUARANTINE_SUFFIX: &str = "corrupt";
+
+/// The kind of item being stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StockpileKind {
+    Triple,
+    Presignature,
+}
+
+impl StockpileKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StockpileKind::Triple => "triple",
+            StockpileKind::Presignature => "presignature",
+        }
+    }
+}
+
+/// Stores serialized unspent items. Implementations must make sure that an item is gone
+/// once `remove` returns successfully, as it would otherwise be reused after a restart.
+pub trait StockpileStorage {
+    fn store(&self, kind: StockpileKind, epoch: u64, id: u64, data: &[u8]) -> Result<()>;
+    fn remove(&self, kind: StockpileKind, epoch: u64, id: u64) -> Result<()>;
+    /// Loads all the items of `kind` stored for `epoch`, removing the ones of other epochs.
+    /// Items that can not be read are skipped rather than failing the whole load.
+    fn load(&self, kind: StockpileKind, epoch: u64) -> Result<Vec<Vec<u8>>>;
+}
+
+#[derive(Default)]
+struct MemoryStockpileStorage {
+    items: Mutex<HashMap<(StockpileKind, u64, u64), Vec<u8>>>,
+}
+
+impl StockpileStorage for MemoryStockpileStorage {
+    fn store(&self, kind: StockpileKind, epoch: u64, id: u64, data: &[u8]) -> Result<()> {
+        self.items
+            .lock()
+            .unwrap()
+            .insert((kind, epoch, id), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, kind: StockpileKind, epoch: u64, id: u64) -> Result<()> {
+        self.items.lock().unwrap().remove(&(kind, epoch, id));
+        Ok(())
+    }
+
+    fn load(&self, kind: StockpileKind, epoch: u64) -> Result<Vec<Vec<u8>>> {
+        let mut items = self.items.lock().unwrap();
+        items.retain(|(item_kind, item_epoch, _), _| *item_kind != kind || *item_epoch == epoch);
+        Ok(items
+            .iter()
+            .filter(|((item_kind, _, _), _)| *item_kind == kind)
+            .map(|(_, data)| data.clone())
+            .collect())
+    }
+}
+
+/// Keeps every item in its own file under `dir`, encrypted with the node's cipher key. The
+/// file name is used as associated data, so an item cannot be passed off as a different one
+/// by renaming its file.
+struct DiskStockpileStorage {
+    dir: PathBuf,
+    cipher_sk: hpke::SecretKey,
+    cipher_pk: hpke::PublicKey,
+}
+
+impl DiskStockpileStorage {
+    fn new(dir: PathBuf, cipher_sk: hpke::SecretKey) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            cipher_pk: cipher_sk.public_key(),
+            cipher_sk,
+        })
+    }
+
+    fn file_name(kind: StockpileKind, epoch: u64, id: u64) -> String {
+        format!("{}-{epoch}-{id}", kind.as_str())
+    }
+
+    /// Reads and decrypts the item stored in the file at `path`.
+    fn read(&self, path: &Path, file_name: &str) -> Result<Vec<u8>> {
+        let ciphered: hpke::Ciphered = serde_json::from_slice(&fs::read(path)?)?;
+        self.cipher_sk
+            .decrypt(&ciphered, file_name.as_bytes())
+            .map_err(|e| SecretStorageError::Encryption(e.to_string()))
+    }
+
+    /// Parses the epoch out of the name of a file holding an item of `kind`.
+    fn parse_epoch(kind: StockpileKind, file_name: &str) -> Option<u64> {
+        let rest = file_name.strip_prefix(kind.as_str())?.strip_prefix('-')?;
+        let (epoch, id) = rest.split_once('-')?;
+        id.parse::<u64>().ok()?;
+        epoch.parse().ok()
+    }
+}
+
+impl StockpileStorage for DiskStockpileStorage {
+    fn store(&self, kind: StockpileKind, epoch: u64, id: u64, data: &[u8]) -> Result<()> {
+        let file_name = Self::file_name(kind, epoch, id);
+        let ciphered = self
+            .cipher_pk
+            .encrypt(data, file_name.as_bytes())
+            .map_err(|e| SecretStorageError::Encryption(e.to_string()))?;
+        // Write to a temporary file first so that a crash never leaves a truncated item behind.
+        let tmp_path = self.dir.join(format!("{file_name}.tmp"));
+        fs::write(&tmp_path, serde_json::to_vec(&ciphered)?)?;
+        fs::rename(tmp_path, self.dir.join(file_name))?;
+        Ok(())
+    }
+
+    fn remove(&self, kind: StockpileKind, epoch: u64, id: u64) -> Result<()> {
+        match fs::remove_file(self.dir.join(Self::file_name(kind, epoch, id))) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn load(&self, kind: StockpileKind, epoch: u64) -> Result<Vec<Vec<u8>>> {
+        let mut items = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    tracing::warn!(?err, "failed to list a stored item");
+                    continue;
+                }
+            };
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            match Self::parse_epoch(kind, file_name) {
+                Some(item_epoch) if item_epoch == epoch => match self.read(&path, file_name) {
+                    Ok(data) => items.push(data),
+                    // A single unreadable item must not keep the node from using the others.
+                    Err(err) => {
+                        let quarantined = format!("{file_name}.{QUARANTINE_SUFFIX}");
+                        tracing::warn!(
+                            ?err,
+                            kind = kind.as_str(),
+                            file_name,
+                            quarantined,
+                            "skipping stored item that can not be read"
+                        );
+                        if let Err(err) = fs::rename(&path, self.dir.join(&quarantined)) {
+                            tracing::warn!(?err, file_name, "failed to quarantine stored item");
+                        }
+                    }
+                },
+                Some(item_epoch) => {
+                    tracing::info!(
+                        kind = kind.as_str(),
+                        item_epoch,
+                        epoch,
+                        file_name,
+                        "dropping stored item of another epoch"
+                    );
+                    if let Err(err) = fs::remove_file(&path) {
+                        tracing::warn!(?err, file_name, "failed to drop stored item");
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(items)
+    }
+}
+
+pub type StockpileStorageBox = Arc<dyn StockpileStorage + Send + Sync>;
+
+pub fn init(opts: &Options, cipher_sk: hpke::SecretKey) -> Result<StockpileStorageBox> {
+    match &opts.stockpile_dir {
+        Some(dir) => {
+            Ok(Arc::new(DiskStockpileStorage::new(dir.clone(), cipher_sk)?) as StockpileStorageBox)
+        }
+        None => Ok(memory()),
+    }
+}
+
+/// Storage that keeps items in memory only, so they do not survive a restart.
+pub fn memory() -> StockpileStorageBox {
+    Arc::<MemoryStockpileStorage>::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskStockpileStorage, StockpileKind, StockpileStorage};
+    use mpc_keys::hpke;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A storage in a fresh directory, removed again once dropped.
+    struct TestStorage {
+        storage: DiskStockpileStorage,
+    }
+
+    impl TestStorage {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("stockpile-{}", rand::random::<u64>()));
+            let (cipher_sk, _) = hpke::generate();
+            TestStorage {
+                storage: DiskStockpileStorage::new(dir, cipher_sk).unwrap(),
+            }
+        }
+
+        fn path(&self, file_name: &str) -> PathBuf {
+            self.storage.dir.join(file_name)
+        }
+
+        fn file_names(&self) -> Vec<String> {
+            let mut names: Vec<_> = fs::read_dir(&self.storage.dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TestStorage {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.storage.dir);
+        }
+    }
+
+    #[test]
+    fn items_are_stored_encrypted() {
+        let test = TestStorage::new();
+        let data = b"a very secret triple share".to_vec();
+        test.storage
+            .store(StockpileKind::Triple, 1, 7, &data)
+            .unwrap();
+
+        // Only the final file is left behind, and it does not hold the item in the clear.
+        assert_eq!(test.file_names(), vec!["triple-1-7".to_string()]);
+        let stored = fs::read(test.path("triple-1-7")).unwrap();
+        assert!(!stored.windows(data.len()).any(|window| window == data));
+
+        assert_eq!(
+            test.storage.load(StockpileKind::Triple, 1).unwrap(),
+            vec![data]
+        );
+        test.storage.remove(StockpileKind::Triple, 1, 7).unwrap();
+        assert!(test.file_names().is_empty());
+        // Removing an item that is already gone is not an error.
+        test.storage.remove(StockpileKind::Triple, 1, 7).unwrap();
+    }
+
+    #[test]
+    fn items_can_not_be_read_under_another_name_or_key() {
+        let test = TestStorage::new();
+        test.storage
+            .store(StockpileKind::Triple, 1, 7, b"triple")
+            .unwrap();
+        fs::rename(test.path("triple-1-7"), test.path("triple-1-8")).unwrap();
+        assert!(test
+            .storage
+            .load(StockpileKind::Triple, 1)
+            .unwrap()
+            .is_empty());
+        assert_eq!(test.file_names(), vec!["triple-1-8.corrupt".to_string()]);
+
+        let test = TestStorage::new();
+        test.storage
+            .store(StockpileKind::Triple, 1, 7, b"triple")
+            .unwrap();
+        let (other_sk, _) = hpke::generate();
+        let other = DiskStockpileStorage::new(test.storage.dir.clone(), other_sk).unwrap();
+        assert!(other.load(StockpileKind::Triple, 1).unwrap().is_empty());
+        assert_eq!(test.file_names(), vec!["triple-1-7.corrupt".to_string()]);
+    }
+
+    #[test]
+    fn unreadable_items_are_quarantined_and_the_rest_loaded() {
+        let test = TestStorage::new();
+        let storage = &test.storage;
+        for id in 0..3 {
+            storage
+                .store(StockpileKind::Triple, 1, id, &[id as u8])
+                .unwrap();
+        }
+        fs::write(test.path("triple-1-1"), b"not an item").unwrap();
+
+        let mut items = storage.load(StockpileKind::Triple, 1).unwrap();
+        items.sort();
+        assert_eq!(items, vec![vec![0], vec![2]]);
+        assert_eq!(
+            test.file_names(),
+            vec![
+                "triple-1-0".to_string(),
+                "triple-1-1.corrupt".to_string(),
+                "triple-1-2".to_string(),
+            ]
+        );
+        // The quarantined file is left alone from then on.
+        assert_eq!(storage.load(StockpileKind::Triple, 1).unwrap().len(), 2);
+        assert!(test
+            .file_names()
+            .contains(&"triple-1-1.corrupt".to_string()));
+    }
+
+    #[test]
+    fn items_of_other_epochs_are_dropped_on_load() {
+        let test = TestStorage::new();
+        let storage = &test.storage;
+        storage.store(StockpileKind::Triple, 0, 1, b"old").unwrap();
+        storage
+            .store(StockpileKind::Triple, 1, 2, b"current")
+            .unwrap();
+        storage
+            .store(StockpileKind::Presignature, 0, 3, b"old presignature")
+            .unwrap();
+        // Left behind by a crash in the middle of a store, or not ours at all.
+        fs::write(test.path("triple-0-4.tmp"), b"partial").unwrap();
+        fs::write(test.path("notes"), b"unrelated").unwrap();
+
+        assert_eq!(
+            storage.load(StockpileKind::Triple, 1).unwrap(),
+            vec![b"current".to_vec()]
+        );
+        assert_eq!(
+            test.file_names(),
+            vec![
+                "notes".to_string(),
+                "presignature-0-3".to_string(),
+                "triple-0-4.tmp".to_string(),
+                "triple-1-2".to_string(),
+            ]
+        );
+
+        assert!(storage
+            .load(StockpileKind::Presignature, 1)
+            .unwrap()
+            .is_empty());
+        assert!(!test.file_names().contains(&"presignature-0-3".to_string()));
+    }
+
+    #[test]
+    fn epochs_are_parsed_from_file_names() {
+        let parse = DiskStockpileStorage::parse_epoch;
+        assert_eq!(parse(StockpileKind::Triple, "triple-3-7"), Some(3));
+        assert_eq!(
+            parse(StockpileKind::Presignature, "presignature-0-1"),
+            Some(0)
+        );
+        assert_eq!(parse(StockpileKind::Triple, "presignature-3-7"), None);
+        assert_eq!(parse(StockpileKind::Triple, "triple-3-7.tmp"), None);
+        assert_eq!(parse(StockpileKind::Triple, "triple-3-7.corrupt"), None);
+        assert_eq!(parse(StockpileKind::Triple, "triple-x-7"), None);
+        assert_eq!(parse(StockpileKind::Triple, "triple-3"), None);
+    }
+}
@@ -64,7 +64,8 @@ pub struct ReshareProtocol {
     old_participants: Vec<Participant>,
     new_participants: Vec<Participant>,
     me: Participant,
-    threshold: usize,
+    old_threshold: usize,
+    new_threshold: usize,
     private_share: Option<SecretKeyShare>,
     protocol: Arc<RwLock<Box<dyn Protocol<Output = SecretKeyShare> + Send + Sync>>>,
     root_pk: PublicKey,
@@ -91,7 +92,7 @@ impl ReshareProtocol {
         Ok(Self {
             protocol: Arc::new(RwLock::new(Box::new(cait_sith::reshare::<Secp256k1>(
                 &old_participants,
-                contract_state.threshold,
+                contract_state.old_threshold,
                 &new_participants,
                 contract_state.threshold,
                 me,
@@ -100,19 +101,25 @@ impl ReshareProtocol {
             )?))),
             private_share,
             me,
-            threshold: contract_state.threshold,
+            old_threshold: contract_state.old_threshold,
+            new_threshold: contract_state.threshold,
             old_participants,
             new_participants,
             root_pk: contract_state.public_key,
         })
     }
 
+    /// The share held before this resharing, if we were a part of the old participant set.
+    pub fn private_share(&self) -> Option<SecretKeyShare> {
+        self.private_share
+    }
+
     pub async fn refresh(&mut self) -> Result<(), InitializationError> {
         *self.write().await = Box::new(cait_sith::reshare::<Secp256k1>(
             &self.old_participants,
-            self.threshold,
+            self.old_threshold,
             &self.new_participants,
-            self.threshold,
+            self.new_threshold,
             self.me,
             self.private_share,
             self.root_pk,
@@ -4,6 +4,7 @@ use self::error::Error;
 use crate::protocol::message::SignedMessage;
 use crate::protocol::{MpcMessage, NodeState};
 use crate::web::error::Result;
+use anyhow::Context;
 use axum::http::StatusCode;
 use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
@@ -13,6 +14,7 @@ use mpc_keys::hpke::{self, Ciphered};
 use near_crypto::InMemorySigner;
 use near_primitives::transaction::{Action, FunctionCallAction};
 use near_primitives::types::AccountId;
+use prometheus::{Encoder, TextEncoder};
 use serde::{Deserialize, Serialize};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::{mpsc::Sender, RwLock};
@@ -57,6 +59,7 @@ pub async fn run(
         .route("/msg", post(msg))
         .route("/join", post(join))
         .route("/state", get(state))
+        .route("/metrics", get(metrics))
         .layer(Extension(Arc::new(axum_state)));
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
@@ -145,8 +148,15 @@ async fn join(
 pub enum StateView {
     Running {
         participants: Vec<Participant>,
+        paused: bool,
         triple_count: usize,
+        /// Triples assigned to this node.
+        triple_mine_count: usize,
+        triple_generating_count: usize,
         presignature_count: usize,
+        /// Presignatures assigned to this node.
+        presignature_mine_count: usize,
+        presignature_generating_count: usize,
     },
     NotRunning,
 }
@@ -157,14 +167,27 @@ async fn state(Extension(state): Extension<Arc<AxumState>>) -> Result<Json<State
     let protocol_state = state.protocol_state.read().await;
     match &*protocol_state {
         NodeState::Running(state) => {
-            let triple_count = state.triple_manager.read().await.len();
-            let presignature_count = state.presignature_manager.read().await.len();
+            let triple_manager = state.triple_manager.read().await;
+            let triple_count = triple_manager.len();
+            let triple_mine_count = triple_manager.my_len();
+            let triple_generating_count = triple_manager.generating_len();
+            drop(triple_manager);
+            let presignature_manager = state.presignature_manager.read().await;
+            let presignature_count = presignature_manager.len();
+            let presignature_mine_count = presignature_manager.my_len();
+            let presignature_generating_count = presignature_manager.generating_len();
+            drop(presignature_manager);
 
             tracing::debug!("not running, state unavailable");
             Ok(Json(StateView::Running {
                 participants: state.participants.keys().cloned().collect(),
+                paused: state.paused,
                 triple_count,
+                triple_mine_count,
+                triple_generating_count,
                 presignature_count,
+                presignature_mine_count,
+                presignature_generating_count,
             }))
         }
         _ => {
@@ -173,3 +196,30 @@ async fn state(Extension(state): Extension<Arc<AxumState>>) -> Result<Json<State
         }
     }
 }
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn metrics() -> (StatusCode, String) {
+    let grab_metrics = || {
+        let encoder = TextEncoder::new();
+        let mut buffer = vec![];
+        encoder
+            .encode(&prometheus::gather(), &mut buffer)
+            .with_context(|| "failed to encode metrics")?;
+
+        let response =
+            String::from_utf8(buffer).with_context(|| "failed to convert bytes to string")?;
+
+        Ok::<String, anyhow::Error>(response)
+    };
+
+    match grab_metrics() {
+        Ok(response) => (StatusCode::OK, response),
+        Err(err) => {
+            tracing::error!("failed to generate prometheus metrics: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to generate prometheus metrics".to_string(),
+            )
+        }
+    }
+}